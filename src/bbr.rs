@@ -0,0 +1,490 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+use crate::congestion::{initial_window, AckEvent, CongestionControl};
+
+//2/ln(2), the smallest gain that still doubles the delivery rate every round in Startup
+const HIGH_GAIN: f64 = 2.885;
+const DRAIN_GAIN: f64 = 1.0 / HIGH_GAIN;
+const CWND_GAIN: f64 = 2.0;
+//ProbeBW spends one round probing for more bandwidth, one draining the queue that left and then
+//cruises for six
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+//the bottleneck bandwidth is the highest delivery rate seen over this many rounds
+const BANDWIDTH_FILTER_ROUNDS: u64 = 10;
+//min RTT is re-probed when it hasn't been seen again for this long
+const MIN_RTT_FILTER: Duration = Duration::from_secs(10);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+const MINIMUM_CWND_SEGMENTS: usize = 4;
+//Startup ends once three rounds in a row fail to grow the bandwidth estimate by a quarter
+const FULL_BANDWIDTH_GROWTH: f64 = 1.25;
+const FULL_BANDWIDTH_ROUNDS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Startup,
+    Drain,
+    ProbeBandwidth,
+    ProbeRtt,
+}
+
+//BBR v1 as in draft-cardwell-iccrg-bbr-congestion-control-00. Rather than reacting to loss it keeps
+//a model of the path, the bottleneck bandwidth and the round trip propagation delay, paces at the
+//bandwidth and keeps about two bandwidth-delay products in flight.
+pub struct Bbr {
+    maximum_segment_size: usize,
+    mode: Mode,
+    cwnd: usize,
+    //cwnd from before loss recovery or ProbeRTT cut it down, restored afterwards
+    prior_cwnd: usize,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    //(round, rate) pairs with rates strictly decreasing, the front is the max over the window
+    bandwidth_filter: VecDeque<(u64, u64)>,
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Instant,
+    //round trips are counted in delivered data, one ends once what was sent at its start is delivered
+    round_count: u64,
+    next_round_delivered: u64,
+    round_start: bool,
+    //Startup's bandwidth growth check
+    filled_pipe: bool,
+    full_bandwidth: u64,
+    full_bandwidth_count: u32,
+    cycle_index: usize,
+    cycle_stamp: Instant,
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    in_recovery: bool,
+}
+
+impl Bbr {
+
+    pub fn new(maximum_segment_size: usize, now: Instant) -> Self {
+        Bbr {
+            maximum_segment_size,
+            mode: Mode::Startup,
+            cwnd: initial_window(maximum_segment_size),
+            prior_cwnd: 0,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            bandwidth_filter: VecDeque::new(),
+            min_rtt: None,
+            min_rtt_stamp: now,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            filled_pipe: false,
+            full_bandwidth: 0,
+            full_bandwidth_count: 0,
+            cycle_index: 0,
+            cycle_stamp: now,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            in_recovery: false,
+        }
+    }
+
+    fn bottleneck_bandwidth(&self) -> u64 {
+        self.bandwidth_filter.front().map_or(0, |(_, rate)| *rate)
+    }
+
+    fn minimum_cwnd(&self) -> usize {
+        MINIMUM_CWND_SEGMENTS * self.maximum_segment_size
+    }
+
+    //gain times the estimated bandwidth-delay product, the initial window until there is a model
+    fn bdp(&self, gain: f64) -> usize {
+        match self.min_rtt {
+            Some(min_rtt) if self.bottleneck_bandwidth() > 0 => {
+                (gain * self.bottleneck_bandwidth() as f64 * min_rtt.as_secs_f64()) as usize
+            },
+            _ => (gain * initial_window(self.maximum_segment_size) as f64) as usize,
+        }
+    }
+
+    fn update_round(&mut self, event: &AckEvent) {
+        self.round_start = false;
+        if let Some(rate_sample) = &event.rate_sample {
+            if rate_sample.prior_delivered >= self.next_round_delivered {
+                self.next_round_delivered = event.delivered;
+                self.round_count += 1;
+                self.round_start = true;
+            }
+        }
+    }
+
+    //an application limited sample only says the path can do at least that much, so it is only
+    //used when it beats the current estimate
+    fn update_bandwidth(&mut self, event: &AckEvent) {
+        let Some(rate_sample) = &event.rate_sample else {
+            return;
+        };
+        if rate_sample.is_app_limited && rate_sample.delivery_rate < self.bottleneck_bandwidth() {
+            return;
+        }
+        while matches!(self.bandwidth_filter.back(), Some((_, rate)) if *rate <= rate_sample.delivery_rate) {
+            self.bandwidth_filter.pop_back();
+        }
+        self.bandwidth_filter.push_back((self.round_count, rate_sample.delivery_rate));
+        while matches!(self.bandwidth_filter.front(), Some((round, _)) if round + BANDWIDTH_FILTER_ROUNDS <= self.round_count) {
+            self.bandwidth_filter.pop_front();
+        }
+    }
+
+    fn check_full_pipe(&mut self, event: &AckEvent) {
+        if self.filled_pipe || !self.round_start || event.rate_sample.is_some_and(|rate_sample| rate_sample.is_app_limited) {
+            return;
+        }
+        let bandwidth = self.bottleneck_bandwidth();
+        if bandwidth as f64 >= self.full_bandwidth as f64 * FULL_BANDWIDTH_GROWTH {
+            self.full_bandwidth = bandwidth;
+            self.full_bandwidth_count = 0;
+            return;
+        }
+        self.full_bandwidth_count += 1;
+        if self.full_bandwidth_count >= FULL_BANDWIDTH_ROUNDS {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn enter_probe_bandwidth(&mut self, now: Instant) {
+        self.mode = Mode::ProbeBandwidth;
+        self.cwnd_gain = CWND_GAIN;
+        //start anywhere but the draining phase, so competing flows don't probe in lockstep
+        let random = RandomState::new().build_hasher().finish() as usize;
+        self.cycle_index = match random % (PACING_GAIN_CYCLE.len() - 1) {
+            0 => 0,
+            index => index + 1,
+        };
+        self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+        self.cycle_stamp = now;
+    }
+
+    fn check_drain(&mut self, bytes_in_flight: usize, now: Instant) {
+        if self.mode == Mode::Startup && self.filled_pipe {
+            self.mode = Mode::Drain;
+            self.pacing_gain = DRAIN_GAIN;
+            self.cwnd_gain = HIGH_GAIN;
+        }
+        if self.mode == Mode::Drain && bytes_in_flight <= self.bdp(1.0) {
+            self.enter_probe_bandwidth(now);
+        }
+    }
+
+    //a phase lasts at least one min RTT. Probing up carries on until the extra data is actually in
+    //flight or loss says there's no more room, draining ends as soon as the queue is gone.
+    fn update_cycle_phase(&mut self, bytes_in_flight: usize, now: Instant) {
+        if self.mode != Mode::ProbeBandwidth {
+            return;
+        }
+        let phase_over = now.saturating_duration_since(self.cycle_stamp) > self.min_rtt.unwrap_or_default();
+        let next = if self.pacing_gain > 1.0 {
+            phase_over && (self.in_recovery || bytes_in_flight >= self.bdp(self.pacing_gain))
+        } else if self.pacing_gain < 1.0 {
+            phase_over || bytes_in_flight <= self.bdp(1.0)
+        } else {
+            phase_over
+        };
+        if next {
+            self.cycle_index = (self.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+            self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+            self.cycle_stamp = now;
+        }
+    }
+
+    fn update_min_rtt(&mut self, event: &AckEvent, bytes_in_flight: usize) {
+        let expired = event.now.saturating_duration_since(self.min_rtt_stamp) > MIN_RTT_FILTER;
+        if let Some(rtt) = event.rtt {
+            if self.min_rtt.is_none_or(|min_rtt| rtt <= min_rtt) || expired {
+                self.min_rtt = Some(rtt);
+                self.min_rtt_stamp = event.now;
+            }
+        }
+        if expired && self.mode != Mode::ProbeRtt {
+            //drain the queue down to a handful of segments so the real propagation delay shows
+            self.mode = Mode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = 1.0;
+            self.prior_cwnd = self.prior_cwnd.max(self.cwnd);
+            self.probe_rtt_done_stamp = None;
+        }
+        if self.mode != Mode::ProbeRtt {
+            return;
+        }
+        match self.probe_rtt_done_stamp {
+            None if bytes_in_flight <= self.minimum_cwnd() => {
+                self.probe_rtt_done_stamp = Some(event.now + PROBE_RTT_DURATION);
+                self.probe_rtt_round_done = false;
+                self.next_round_delivered = event.delivered;
+            },
+            None => {},
+            Some(done_stamp) => {
+                if self.round_start {
+                    self.probe_rtt_round_done = true;
+                }
+                if self.probe_rtt_round_done && event.now >= done_stamp {
+                    self.min_rtt_stamp = event.now;
+                    self.cwnd = self.cwnd.max(self.prior_cwnd);
+                    self.prior_cwnd = 0;
+                    if self.filled_pipe {
+                        self.enter_probe_bandwidth(event.now);
+                    } else {
+                        self.mode = Mode::Startup;
+                        self.pacing_gain = HIGH_GAIN;
+                        self.cwnd_gain = HIGH_GAIN;
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_cwnd(&mut self, event: &AckEvent, bytes_in_flight: usize) {
+        let delivered_bytes = event.delivered_bytes;
+        if self.in_recovery {
+            //packet conservation, only send as much as is leaving the network
+            self.cwnd = self.cwnd.max(bytes_in_flight + delivered_bytes);
+        } else {
+            //a few extra segments of headroom for delayed and stretched ACKs
+            let target = self.bdp(self.cwnd_gain) + 3 * self.maximum_segment_size;
+            if self.filled_pipe {
+                self.cwnd = (self.cwnd + delivered_bytes).min(target);
+            } else if self.cwnd < target || event.delivered < initial_window(self.maximum_segment_size) as u64 {
+                self.cwnd += delivered_bytes;
+            }
+        }
+        self.cwnd = self.cwnd.max(self.minimum_cwnd());
+        if self.mode == Mode::ProbeRtt {
+            self.cwnd = self.cwnd.min(self.minimum_cwnd());
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+
+    fn name(&self) -> &'static str {
+        "bbr"
+    }
+
+    fn set_maximum_segment_size(&mut self, maximum_segment_size: usize) {
+        self.maximum_segment_size = maximum_segment_size;
+        self.cwnd = initial_window(maximum_segment_size);
+    }
+
+    fn on_ack(&mut self, event: &AckEvent) {
+        self.in_recovery = event.in_recovery;
+        let bytes_in_flight = event.bytes_in_flight.saturating_sub(event.delivered_bytes);
+        self.update_round(event);
+        self.update_bandwidth(event);
+        self.update_cycle_phase(bytes_in_flight, event.now);
+        self.check_full_pipe(event);
+        self.check_drain(bytes_in_flight, event.now);
+        self.update_min_rtt(event, bytes_in_flight);
+        self.set_cwnd(event, bytes_in_flight);
+    }
+
+    //loss isn't a congestion signal to BBR, but the window is held to what is still in flight
+    //until recovery is over
+    fn on_loss(&mut self, bytes_in_flight: usize, _now: Instant) {
+        self.in_recovery = true;
+        self.prior_cwnd = self.prior_cwnd.max(self.cwnd);
+        self.cwnd = bytes_in_flight.max(self.minimum_cwnd());
+    }
+
+    fn on_recovery_end(&mut self, _bytes_in_flight: usize, _now: Instant) {
+        self.in_recovery = false;
+        self.cwnd = self.cwnd.max(self.prior_cwnd);
+        self.prior_cwnd = 0;
+    }
+
+    fn on_rto(&mut self, _bytes_in_flight: usize, _now: Instant) {
+        self.in_recovery = true;
+        self.prior_cwnd = self.prior_cwnd.max(self.cwnd);
+        self.cwnd = self.maximum_segment_size;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    //not used by BBR, reported as unlimited
+    fn ssthresh(&self) -> usize {
+        usize::MAX
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        let bandwidth = match (self.bottleneck_bandwidth(), self.min_rtt) {
+            (0, Some(min_rtt)) if !min_rtt.is_zero() => (initial_window(self.maximum_segment_size) as f64 / min_rtt.as_secs_f64()) as u64,
+            //no RTT yet either, assume a millisecond
+            (0, _) => initial_window(self.maximum_segment_size) as u64 * 1000,
+            (bandwidth, _) => bandwidth,
+        };
+        Some((self.pacing_gain * bandwidth as f64) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{Bbr, Mode, CWND_GAIN, DRAIN_GAIN, HIGH_GAIN, PACING_GAIN_CYCLE};
+    use crate::congestion::{AckEvent, CongestionControl};
+    use crate::delivery::RateSample;
+
+    const MSS: usize = 1000;
+    const RTT: Duration = Duration::from_millis(100);
+    //bytes per second, a bandwidth-delay product of 100 segments at RTT
+    const BANDWIDTH: u64 = 1_000_000;
+    const BDP: usize = 100_000;
+
+    //feeds a connection ACKs that each start a new round, every one measuring the same bandwidth
+    //unless told otherwise
+    struct Path {
+        bbr: Bbr,
+        now: Instant,
+        delivered: u64,
+    }
+
+    impl Path {
+
+        fn new() -> Self {
+            let now = Instant::now();
+            Path { bbr: Bbr::new(MSS, now), now, delivered: 0 }
+        }
+
+        fn ack(&mut self, delivery_rate: u64, bytes_in_flight: usize) {
+            self.ack_with(delivery_rate, bytes_in_flight, RTT, false);
+        }
+
+        fn ack_with(&mut self, delivery_rate: u64, bytes_in_flight: usize, rtt: Duration, is_app_limited: bool) {
+            let delivered_bytes = 10 * MSS;
+            let prior_delivered = self.delivered;
+            self.delivered += delivered_bytes as u64;
+            self.bbr.on_ack(&AckEvent {
+                now: self.now,
+                acknowledged_bytes: delivered_bytes,
+                delivered_bytes,
+                delivered: self.delivered,
+                bytes_in_flight: bytes_in_flight + delivered_bytes,
+                rtt: Some(rtt),
+                rate_sample: Some(RateSample {
+                    delivery_rate,
+                    prior_delivered,
+                    delivered: delivered_bytes as u64,
+                    interval: rtt,
+                    is_app_limited,
+                }),
+                in_recovery: false,
+            });
+        }
+
+        //through Startup and Drain with the pipe full at BANDWIDTH
+        fn in_probe_bandwidth() -> Self {
+            let mut path = Path::new();
+            for _ in 0..4 {
+                path.ack(BANDWIDTH, 2 * BDP);
+            }
+            path.ack(BANDWIDTH, BDP);
+            assert_eq!(path.bbr.mode, Mode::ProbeBandwidth);
+            path
+        }
+    }
+
+    fn paced_at(gain: f64) -> Option<u64> {
+        Some((gain * BANDWIDTH as f64) as u64)
+    }
+
+    #[test]
+    fn startup_paces_at_high_gain_until_bandwidth_stops_growing() {
+        let mut path = Path::new();
+        path.ack(BANDWIDTH, 2 * BDP);
+        assert_eq!(path.bbr.mode, Mode::Startup);
+        assert_eq!(path.bbr.pacing_rate(), paced_at(HIGH_GAIN));
+
+        //still growing by a quarter a round, keep going
+        path.ack(2 * BANDWIDTH, 2 * BDP);
+        assert_eq!(path.bbr.mode, Mode::Startup);
+        assert_eq!(path.bbr.full_bandwidth, 2 * BANDWIDTH);
+
+        //three rounds without that growth and the pipe is full, the queue Startup built drains
+        for _ in 0..3 {
+            path.ack(2 * BANDWIDTH, 4 * BDP);
+        }
+        assert_eq!(path.bbr.mode, Mode::Drain);
+        assert_eq!(path.bbr.pacing_rate(), Some((DRAIN_GAIN * 2.0 * BANDWIDTH as f64) as u64));
+
+        //down to one bandwidth-delay product in flight at the new rate, on to ProbeBW at any gain
+        //but the draining one
+        path.ack(2 * BANDWIDTH, 2 * BDP);
+        assert_eq!(path.bbr.mode, Mode::ProbeBandwidth);
+        assert_ne!(path.bbr.pacing_gain, PACING_GAIN_CYCLE[1]);
+        assert_eq!(path.bbr.cwnd_gain, CWND_GAIN);
+    }
+
+    #[test]
+    fn probe_bandwidth_keeps_two_bdps_in_flight() {
+        let mut path = Path::in_probe_bandwidth();
+        for _ in 0..50 {
+            path.ack(BANDWIDTH, BDP);
+        }
+        //plus a little headroom for delayed ACKs
+        assert_eq!(path.bbr.cwnd(), 2 * BDP + 3 * MSS);
+    }
+
+    //1.25 until the extra is in flight, 0.75 until the queue is gone, then six rounds at 1
+    #[test]
+    fn pacing_gain_cycles_through_probing_draining_and_cruising() {
+        let mut path = Path::in_probe_bandwidth();
+        path.bbr.cycle_index = 0;
+        path.bbr.pacing_gain = PACING_GAIN_CYCLE[0];
+        path.bbr.cycle_stamp = path.now;
+        path.now += RTT + Duration::from_millis(1);
+        path.ack(BANDWIDTH, BDP);
+        assert_eq!(path.bbr.pacing_rate(), paced_at(1.25));
+        path.ack(BANDWIDTH, BDP * 5 / 4);
+        assert_eq!(path.bbr.pacing_rate(), paced_at(0.75));
+        path.ack(BANDWIDTH, BDP);
+        assert_eq!(path.bbr.pacing_rate(), paced_at(1.0));
+        for _ in 0..6 {
+            path.now += RTT + Duration::from_millis(1);
+            path.ack(BANDWIDTH, BDP);
+        }
+        assert_eq!(path.bbr.pacing_rate(), paced_at(1.25));
+    }
+
+    #[test]
+    fn bandwidth_is_the_max_over_ten_rounds() {
+        let mut path = Path::in_probe_bandwidth();
+        path.ack(2 * BANDWIDTH, BDP);
+        //an application limited sample can't pull the estimate down
+        path.ack_with(BANDWIDTH / 10, BDP, RTT, true);
+        for _ in 0..9 {
+            assert_eq!(path.bbr.bottleneck_bandwidth(), 2 * BANDWIDTH);
+            path.ack(BANDWIDTH, BDP);
+        }
+        assert_eq!(path.bbr.bottleneck_bandwidth(), BANDWIDTH);
+    }
+
+    #[test]
+    fn loss_holds_the_window_to_what_is_in_flight_until_recovery_ends() {
+        let mut path = Path::in_probe_bandwidth();
+        let cwnd = path.bbr.cwnd();
+        path.bbr.on_loss(50 * MSS, path.now);
+        assert_eq!(path.bbr.cwnd(), 50 * MSS);
+        path.bbr.on_loss(MSS, path.now);
+        assert_eq!(path.bbr.cwnd(), 4 * MSS);
+        path.bbr.on_recovery_end(MSS, path.now);
+        assert_eq!(path.bbr.cwnd(), cwnd);
+    }
+
+    #[test]
+    fn a_stale_min_rtt_sends_it_to_probe_rtt() {
+        let mut path = Path::in_probe_bandwidth();
+        path.now += Duration::from_secs(11);
+        path.ack_with(BANDWIDTH, BDP, 2 * RTT, false);
+        assert_eq!(path.bbr.mode, Mode::ProbeRtt);
+        assert_eq!(path.bbr.min_rtt, Some(2 * RTT));
+        assert_eq!(path.bbr.cwnd(), 4 * MSS);
+        assert_eq!(path.bbr.pacing_rate(), paced_at(1.0));
+    }
+}
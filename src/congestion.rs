@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+use crate::bbr::Bbr;
+use crate::cubic::Cubic;
+use crate::delivery::RateSample;
+use crate::newreno::NewReno;
+
+//RFC 6928 initial window, ten segments but never more than 14600 bytes unless the MSS forces it
+pub fn initial_window(maximum_segment_size: usize) -> usize {
+    (10 * maximum_segment_size).min((2 * maximum_segment_size).max(14600))
+}
+
+//RFC 5681 section 3.1, what ssthresh drops to once loss is detected
+pub fn loss_threshold(bytes_in_flight: usize, maximum_segment_size: usize) -> usize {
+    (bytes_in_flight / 2).max(2 * maximum_segment_size)
+}
+
+//what a congestion controller gets told about every ACK that moved snd.una forward or SACKed
+//something new
+pub struct AckEvent {
+    pub now: Instant,
+    //bytes newly cumulatively acknowledged by this ACK
+    pub acknowledged_bytes: usize,
+    //bytes newly delivered by this ACK, SACKed data included
+    pub delivered_bytes: usize,
+    //total delivered over the connection's lifetime, this ACK included
+    pub delivered: u64,
+    //what was outstanding just before it arrived
+    pub bytes_in_flight: usize,
+    pub rtt: Option<Duration>,
+    pub rate_sample: Option<RateSample>,
+    //loss recovery is under way, the window is not grown during it
+    pub in_recovery: bool,
+}
+
+//the sender side window logic of a connection. Connection keeps track of what is in flight and
+//when loss happens, implementations only decide how big the congestion window should be.
+pub trait CongestionControl {
+    fn name(&self) -> &'static str;
+
+    //the MSS is only known once the handshake is done, the window is reset to the initial one
+    fn set_maximum_segment_size(&mut self, maximum_segment_size: usize);
+
+    fn on_ack(&mut self, event: &AckEvent);
+
+    //entering loss recovery after a fast retransmit or SACK loss detection
+    fn on_loss(&mut self, bytes_in_flight: usize, now: Instant);
+
+    //everything outstanding when recovery started has been acknowledged
+    fn on_recovery_end(&mut self, _bytes_in_flight: usize, _now: Instant) {}
+
+    //the retransmission timer went off
+    fn on_rto(&mut self, bytes_in_flight: usize, now: Instant);
+
+    fn cwnd(&self) -> usize;
+
+    //bytes per second segments should be spread out at, None sends them as fast as the window allows
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    fn ssthresh(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    Cubic,
+    Bbr,
+}
+
+impl CongestionAlgorithm {
+
+    pub fn from_name(name: &str) -> Result<Self, &'static str> {
+        match name.to_ascii_lowercase().as_str() {
+            "newreno" | "reno" => Ok(CongestionAlgorithm::NewReno),
+            "cubic" => Ok(CongestionAlgorithm::Cubic),
+            "bbr" => Ok(CongestionAlgorithm::Bbr),
+            _ => Err("[ERROR]: unknown congestion control algorithm"),
+        }
+    }
+
+    //as it goes over the unix socket
+    pub fn from_byte(byte: u8) -> Result<Self, &'static str> {
+        match byte {
+            1 => Ok(CongestionAlgorithm::NewReno),
+            2 => Ok(CongestionAlgorithm::Cubic),
+            3 => Ok(CongestionAlgorithm::Bbr),
+            _ => Err("[ERROR]: unknown congestion control algorithm"),
+        }
+    }
+
+    pub fn create(self, maximum_segment_size: usize) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(maximum_segment_size)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(maximum_segment_size)),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new(maximum_segment_size, Instant::now())),
+        }
+    }
+}
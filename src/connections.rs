@@ -1,146 +1,1702 @@
 use std::net::Ipv4Addr;
-use std::default::Default;
-use crate::Ipv4;
-use crate::Tcp;
+use std::time::{Duration, Instant};
+use crate::congestion::{AckEvent, CongestionAlgorithm, CongestionControl};
+use crate::delivery::DeliveryRateEstimator;
+use crate::events::SocketOption;
+use crate::ipv4::Ipv4;
+use crate::mitigations::{self, Mitigation};
+use crate::tcp::Tcp;
+use crate::tcp::{options_length, TcpOption, MAXIMUM_OPTIONS_LENGTH, FLAG_ACK, FLAG_FIN, FLAG_PSH, FLAG_RST, FLAG_SYN};
+use crate::reassembly::ReassemblyQueue;
+use crate::seqnum::SeqNum;
+use crate::retransmission::{RetransmissionQueue, RtoEstimator, SentSegment, DUPLICATE_THRESHOLD};
+use crate::stack::MTU;
+use crate::syncookies::SynCookie;
+use crate::timer::{ConnectionTimers, TimerKind, TICK};
+use crate::timestamps::Timestamps;
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+//how much received data we are willing to hold before the application reads it, unless told otherwise
+pub const DEFAULT_RECEIVE_BUFFER_SIZE: usize = 262144;
+//RFC 7323 section 2.3, the largest shift a window scale option may carry. Together with the 16 bit
+//window field that puts a ceiling on how big a receive buffer is still useful.
+const MAXIMUM_WINDOW_SHIFT: u8 = 14;
+const MAXIMUM_RECEIVE_BUFFER_SIZE: usize = (u16::MAX as usize) << MAXIMUM_WINDOW_SHIFT;
+//how much unacknowledged and unsent data a connection will hold for the application
+const SEND_BUFFER_SIZE: usize = 262144;
+//RFC 9293 default send MSS when the peer's SYN doesn't carry one
+const DEFAULT_MAXIMUM_SEGMENT_SIZE: usize = 536;
+//what we advertise, a full MTU less the IPv4 and TCP headers
+const LOCAL_MAXIMUM_SEGMENT_SIZE: usize = MTU - 40;
+//same floor as linux, a peer advertising less would have us send absurdly small segments
+const MINIMUM_MAXIMUM_SEGMENT_SIZE: usize = 88;
+//how long an active open may sit in SynSent before we give up on it
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(75);
+//retransmissions of a single segment before the connection is aborted, same defaults as linux's
+//tcp_syn_retries and tcp_retries2
+const MAXIMUM_SYN_RETRANSMISSIONS: u32 = 6;
+const MAXIMUM_RETRANSMISSIONS: u32 = 15;
+//RFC 8985 section 7.2, what a tail loss probe waits for before any RTT is known, and the worst
+//case delayed ACK allowed for when only one segment is outstanding
+const INITIAL_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const WORST_CASE_DELAYED_ACK: Duration = Duration::from_millis(200);
+//how long an ACK for in order data may be held back for, unless the stack is configured otherwise
+pub const DEFAULT_DELAYED_ACKNOWLEDGEMENT_TIMEOUT: Duration = Duration::from_millis(40);
+const MAXIMUM_DELAYED_ACKNOWLEDGEMENT_TIMEOUT: Duration = Duration::from_millis(500);
+//RFC 1122 section 4.2.2.17, window probes back off exponentially up to this
+const MAXIMUM_PERSIST_TIMEOUT: Duration = Duration::from_secs(60);
+//Linux's keepalive defaults: two hours idle, then nine probes 75 seconds apart
+const DEFAULT_KEEPALIVE_IDLE: Duration = Duration::from_secs(7200);
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(75);
+const DEFAULT_KEEPALIVE_COUNT: u32 = 9;
+//how long a connection lingers in TIME_WAIT, 2MSL. Linux's 60 seconds rather than RFC 9293's 4 minutes.
+pub const DEFAULT_TIME_WAIT_DURATION: Duration = Duration::from_secs(60);
+//like Linux, a corked socket doesn't sit on a partial segment for longer than this
+const CORK_TIMEOUT: Duration = Duration::from_millis(200);
+
+//the address our side of the tunnel answers to, the kernel end of mytun is 10.0.0.1
+pub const LOCAL_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+//src_* is always the remote peer and dest_* is always us, i.e. the pair as it appears on an inbound
+//segment. Outbound segments simply swap the two around.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
 pub struct SocketPair {
     pub src_ip: Ipv4Addr,
     pub dest_ip: Ipv4Addr,
-    pub src_port: u16, 
+    pub src_port: u16,
     pub dest_port: u16
 }
 
+//RFC 9293 section 3.3.1 send sequence variables
+#[derive(Default)]
+struct SendSequenceSpace {
+    unacknowledged: SeqNum,
+    next: SeqNum,
+    //already scaled, i.e. in bytes
+    window: u32,
+    //the peer's window scale, applied to every window it advertises outside of its SYN
+    window_shift: u8,
+    //MAX.SND.WND, the largest window the peer has ever offered
+    maximum_window: u32,
+    //sequence and ack number of the segment used for the last window update
+    window_update_sequence: SeqNum,
+    window_update_acknowledgement: SeqNum,
+    initial_sequence_number: SeqNum,
+}
+
+//RFC 9293 section 3.3.1 receive sequence variables
+#[derive(Default)]
+struct ReceiveSequenceSpace {
+    next: SeqNum,
+    window: u32,
+    //our window scale, windows we advertise are shifted right by this much
+    window_shift: u8,
+    initial_sequence_number: SeqNum,
+}
+
+//things the owning unix socket client needs to hear about
+#[derive(Debug, PartialEq)]
+pub enum ConnectionEvent {
+    Established,
+    Reset,
+    TimedOut,
+}
 
 pub struct Connection {
+    socket_pair: SocketPair,
     connection_state: ConnectionState,
-    client_sequence_number: u32,
-    server_sequence_number: u32,
-    client_acknowledgement_number: u32,
-    server_acknowledgement_number: u32,
-    client_window: u16,
-    server_window: u16,
+    //id of the unix socket client this connection belongs to, if any
+    owner: Option<u32>,
+    events: Vec<ConnectionEvent>,
+    timers: ConnectionTimers,
+    retransmission_queue: RetransmissionQueue,
+    rto_estimator: RtoEstimator,
+    //the retransmission timer fired and the earliest unacknowledged segment needs to go out again
+    retransmission_due: bool,
+    //same again for a fast retransmit, the one sent on entering recovery or for a partial ACK
+    fast_retransmit_due: bool,
+    //RFC 5681 duplicate ACKs received in a row for snd.una
+    duplicate_acknowledgements: u32,
+    //the tail loss probe timer fired, one probe is to go out whatever the congestion window says
+    tail_loss_probe_due: bool,
+    //RFC 8985 TLP.end_seq and TLP.is_retrans, set while a probe is outstanding
+    tail_loss_probe_end: Option<SeqNum>,
+    tail_loss_probe_retransmitted: bool,
+    //both sides sent SACK-permitted on their SYN
+    sack_permitted: bool,
+    timestamps: Timestamps,
+    //RFC 6675 RecoveryPoint, set while recovering from loss and cleared once it is acknowledged
+    recovery_point: Option<SeqNum>,
+    congestion_control: Box<dyn CongestionControl>,
+    delivery: DeliveryRateEstimator,
+    //when the pacing rate lets the next segment go out, None while nothing has been paced yet
+    pacing_next_send: Option<Instant>,
+    send: SendSequenceSpace,
+    receive: ReceiveSequenceSpace,
+    //how much received data we hold for the application, the receive window is whatever is left
+    receive_buffer_size: usize,
+    //both sides sent a window scale option on their SYN
+    window_scaling: bool,
+    //largest payload we put in one segment, settled from the peer's MSS option during the handshake
+    maximum_segment_size: usize,
+    //close() has been called, a FIN goes out once the outbound buffer has been sent
+    fin_queued: bool,
+    fin_sent: bool,
+    //set whenever the peer is owed an ACK that hasn't been sent yet, e.g. a window update
+    acknowledgement_pending: bool,
+    //RFC 1122 section 4.2.3.2, in order data is acknowledged every second full segment or once
+    //the delayed ACK timer runs out, whichever is first. Off is like TCP_QUICKACK.
+    delayed_acknowledgement: bool,
+    delayed_acknowledgement_timeout: Duration,
+    //bytes of in order data received since we last sent an ACK
+    received_since_acknowledgement: usize,
+    //what a full segment from the peer looks like, the largest one seen so far (rcv_mss). The
+    //peer's MSS can be well under ours so ours is no good for counting full segments.
+    received_segment_size: usize,
+    //the persist timer went off while the peer's window is shut, a probe is to go out
+    window_probe_due: bool,
+    //probes sent since the peer's window closed, each one doubles the wait for the next
+    persist_backoff: u32,
+    //RFC 1122 section 4.2.3.6, once nothing has been heard for keepalive_idle a probe goes out every
+    //keepalive_interval, and after keepalive_count of them go unanswered the connection is dropped
+    keepalive: bool,
+    keepalive_idle: Duration,
+    keepalive_interval: Duration,
+    keepalive_count: u32,
+    keepalive_probes_sent: u32,
+    keepalive_probe_due: bool,
+    time_wait_duration: Duration,
+    //the connection was torn down without the peer's FIN, a read is an error rather than end of stream
+    aborted: bool,
+    //TCP_NODELAY turns Nagle's algorithm off, TCP_CORK holds back partial segments even when
+    //nothing is in flight. cork_expired lets a corked partial segment out once CORK_TIMEOUT is up.
+    no_delay: bool,
+    corked: bool,
+    cork_expired: bool,
     inbound_buffer: Vec<u8>,
-    outbound_buffer: Vec<u8>
+    //segments that arrived ahead of receive.next, moved to inbound_buffer as the gaps fill
+    reassembly_queue: ReassemblyQueue,
+    //everything from send.unacknowledged onwards, sent or not. send_buffer_start is the sequence
+    //number of outbound_buffer[0]
+    outbound_buffer: Vec<u8>,
+    send_buffer_start: SeqNum
 }
 
 
+impl Connection {
+
+    //a connection created because a SYN turned up on one of our listening ports
+    pub fn new_passive(socket_pair: SocketPair, initial_sequence_number: SeqNum) -> Self {
+        Self::new(socket_pair, ConnectionState::Listen, None, initial_sequence_number)
+    }
 
-impl Default for Connection {
-    fn default() -> Self {
+    //a connection we are opening on behalf of a unix socket client, nothing is sent until open()
+    pub fn new_active(socket_pair: SocketPair, owner: u32, initial_sequence_number: SeqNum) -> Self {
+        Self::new(socket_pair, ConnectionState::Closed, Some(owner), initial_sequence_number)
+    }
+
+    fn new(socket_pair: SocketPair, connection_state: ConnectionState, owner: Option<u32>, initial_sequence_number: SeqNum) -> Self {
         Connection {
-            client_sequence_number : 0,
-            server_sequence_number : 0,
-            connection_state : ConnectionState::Unitialized,
-            client_acknowledgement_number : 0,
-            server_acknowledgement_number : 0,
-            server_window: 65535,
-            client_window: 0,
+            socket_pair,
+            connection_state,
+            owner,
+            events: Vec::new(),
+            timers: ConnectionTimers::default(),
+            retransmission_queue: RetransmissionQueue::default(),
+            rto_estimator: RtoEstimator::default(),
+            retransmission_due: false,
+            fast_retransmit_due: false,
+            duplicate_acknowledgements: 0,
+            tail_loss_probe_due: false,
+            tail_loss_probe_end: None,
+            tail_loss_probe_retransmitted: false,
+            sack_permitted: false,
+            timestamps: Timestamps::new(0, Instant::now()),
+            recovery_point: None,
+            congestion_control: CongestionAlgorithm::default().create(DEFAULT_MAXIMUM_SEGMENT_SIZE),
+            delivery: DeliveryRateEstimator::new(Instant::now()),
+            pacing_next_send: None,
+            send: SendSequenceSpace {
+                initial_sequence_number,
+                ..SendSequenceSpace::default()
+            },
+            receive: ReceiveSequenceSpace {
+                window: DEFAULT_RECEIVE_BUFFER_SIZE as u32,
+                ..ReceiveSequenceSpace::default()
+            },
+            receive_buffer_size: DEFAULT_RECEIVE_BUFFER_SIZE,
+            window_scaling: false,
+            maximum_segment_size: DEFAULT_MAXIMUM_SEGMENT_SIZE,
+            fin_queued: false,
+            fin_sent: false,
+            acknowledgement_pending: false,
+            delayed_acknowledgement: true,
+            delayed_acknowledgement_timeout: DEFAULT_DELAYED_ACKNOWLEDGEMENT_TIMEOUT,
+            received_since_acknowledgement: 0,
+            received_segment_size: DEFAULT_MAXIMUM_SEGMENT_SIZE,
+            window_probe_due: false,
+            persist_backoff: 0,
+            keepalive: false,
+            keepalive_idle: DEFAULT_KEEPALIVE_IDLE,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            keepalive_count: DEFAULT_KEEPALIVE_COUNT,
+            keepalive_probes_sent: 0,
+            keepalive_probe_due: false,
+            time_wait_duration: DEFAULT_TIME_WAIT_DURATION,
+            aborted: false,
+            no_delay: false,
+            corked: false,
+            cork_expired: false,
             inbound_buffer: Vec::new(),
-            outbound_buffer: Vec::new()
+            reassembly_queue: ReassemblyQueue::default(),
+            outbound_buffer: Vec::new(),
+            send_buffer_start: SeqNum::default()
         }
     }
-}
 
+    //only before anything has been sent, the window scale we offer is worked out from it
+    pub fn set_receive_buffer_size(&mut self, size: usize) -> Result<(), String> {
+        if !matches!(self.connection_state, ConnectionState::Listen | ConnectionState::Closed) {
+            return Err("[ERROR]: receive buffer size can only be changed before the handshake".to_string());
+        }
+        self.receive_buffer_size = size.clamp(LOCAL_MAXIMUM_SEGMENT_SIZE, MAXIMUM_RECEIVE_BUFFER_SIZE);
+        self.update_receive_window();
+        Ok(())
+    }
 
-impl Connection {
+    //RFC 7323 section 5.4, timestamps start from a per-connection offset
+    pub fn set_timestamp_offset(&mut self, offset: u32) {
+        self.timestamps.set_offset(offset);
+    }
 
-    pub fn process_incoming(&mut self, incoming_ipv4header: &Ipv4, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> Result<usize, String> {
-        
-        self.client_window = incoming_tcpheader.window_size();
+    //swaps the congestion controller, starting it over from the initial window
+    pub fn set_congestion_control(&mut self, algorithm: CongestionAlgorithm) {
+        self.congestion_control = algorithm.create(self.maximum_segment_size);
+        println!("[INFO]: {}:{} now uses {} congestion control",
+            self.socket_pair.src_ip, self.socket_pair.src_port, self.congestion_control.name());
+    }
 
+    pub fn set_time_wait_duration(&mut self, duration: Duration) {
+        self.time_wait_duration = duration;
+    }
+
+    //RFC 1122 caps the delay at half a second
+    pub fn set_delayed_acknowledgement_timeout(&mut self, timeout: Duration) {
+        self.delayed_acknowledgement_timeout = timeout.min(MAXIMUM_DELAYED_ACKNOWLEDGEMENT_TIMEOUT);
+    }
+
+    pub fn set_option(&mut self, option: SocketOption, now: Instant) {
+        match option {
+            SocketOption::KeepAlive(enabled) => {
+                self.keepalive = enabled;
+                self.restart_keepalive(now);
+            },
+            SocketOption::KeepIdle(idle) => {
+                self.keepalive_idle = idle;
+                self.restart_keepalive(now);
+            },
+            SocketOption::KeepInterval(interval) => self.keepalive_interval = interval,
+            SocketOption::KeepCount(count) => self.keepalive_count = count,
+            SocketOption::CongestionControl(algorithm) => self.set_congestion_control(algorithm),
+            SocketOption::NoDelay(enabled) => self.no_delay = enabled,
+            SocketOption::Cork(enabled) => {
+                self.corked = enabled;
+                self.cork_expired = false;
+                if !enabled {
+                    self.timers.cancel(TimerKind::Cork);
+                }
+            },
+            SocketOption::QuickAck(enabled) => {
+                self.delayed_acknowledgement = !enabled;
+                //anything held back goes out on the next flush
+                if enabled && self.received_since_acknowledgement > 0 {
+                    self.acknowledgement_pending = true;
+                }
+            },
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.connection_state, ConnectionState::Closed)
+    }
+
+    //a passive open still waiting for the ACK of our SYN-ACK
+    pub fn is_half_open(&self) -> bool {
+        matches!(self.connection_state, ConnectionState::SynReceived) && self.owner.is_none()
+    }
+
+    pub fn was_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    pub fn has_pending_data(&self) -> bool {
+        !self.inbound_buffer.is_empty()
+    }
+
+    //true once the peer has sent its FIN, a read on an empty buffer is then end of stream
+    pub fn peer_has_closed(&self) -> bool {
+        matches!(
+            self.connection_state,
+            ConnectionState::CloseWait
+                | ConnectionState::Closing
+                | ConnectionState::LastAck
+                | ConnectionState::TimeWait
+                | ConnectionState::Closed
+        )
+    }
+
+    //hand received data to the application, the window that frees up is advertised on the next flush
+    pub fn read(&mut self, max_length: usize) -> Vec<u8> {
+        let length = max_length.min(self.inbound_buffer.len());
+        let data: Vec<u8> = self.inbound_buffer.drain(..length).collect();
+        let previous_window = self.receive.window;
+        self.update_receive_window();
+        if previous_window < self.receive.window && (previous_window as usize) < LOCAL_MAXIMUM_SEGMENT_SIZE {
+            self.acknowledgement_pending = true;
+        }
+        data
+    }
+
+    //queue application data, it is put on the wire by write_next_segment. Like send(2) this may
+    //accept less than it was given once the outbound buffer is full.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, String> {
+        if self.fin_queued {
+            return Err("[ERROR]: connection closing".to_string());
+        }
         match self.connection_state {
-            ConnectionState::Unitialized => {
-                self.client_sequence_number = incoming_tcpheader.sequence_number();
-                self.client_window = incoming_tcpheader.window_size();
-                self.server_acknowledgement_number = self.client_sequence_number + 1;
-                self.server_sequence_number = Self::set_intial_sequence_number();
-                let mut outbound_tcp_header = Tcp::default();
-                let mut outbound_ipv4_header = Ipv4::new(
-                   incoming_ipv4header.destination_ip(),
-                   incoming_ipv4header.source_ip(),
-                );
-                outbound_tcp_header.set_syn_ack_flags();
-                outbound_tcp_header.set_window(self.server_window);
-                outbound_tcp_header.set_acknowledgement_number(self.server_acknowledgement_number);
-                outbound_tcp_header.set_sequence_number(self.server_sequence_number);
-                let mut serialized_tcp_header = outbound_tcp_header.serialize();
-                let pseduo_header = Tcp::create_checksum_pseudo_header(
-                    incoming_ipv4header.destination_ip(),
-                    incoming_ipv4header.source_ip(),
-                    &serialized_tcp_header,
-                    payload
-                );
-                let tcp_checksum = Tcp::calculate_tcp_checksum(pseduo_header.as_slice(), &serialized_tcp_header, &payload);
-                Tcp::write_checksum(&mut serialized_tcp_header, tcp_checksum);
-                outbound_ipv4_header.set_total_length(20 as u16 + serialized_tcp_header.len() as u16);
-
-                //serialize ipv4 header
-                let mut serialized_ipv4_header = outbound_ipv4_header.serialize();
-                Ipv4::calculate_and_set_checksum(&mut serialized_ipv4_header);
-
-                let ipv4_header_length = serialized_ipv4_header.len();
-                let tcp_header_length = serialized_tcp_header.len();
-                outbound_buffer[ .. ipv4_header_length ].copy_from_slice(serialized_ipv4_header.as_slice());  
-                outbound_buffer[ ipv4_header_length .. ipv4_header_length + tcp_header_length  ].copy_from_slice(serialized_tcp_header.as_slice());  
-
-                self.connection_state = ConnectionState::SynReceived;
-                Ok(outbound_ipv4_header.total_length() as usize)
-
-            }
-            ConnectionState::Listen => todo!(),
-            ConnectionState::SynReceived => {
-                //NO RESPONSE NEEDED HERE
-                if !incoming_tcpheader.is_ack_set()  {
-                    return Err("[ERROR]: expected cliient to acknowledge our servers syn flag".to_string());
+            ConnectionState::SynSent
+            | ConnectionState::SynReceived
+            | ConnectionState::Established
+            | ConnectionState::CloseWait => {
+                let length = data.len().min(SEND_BUFFER_SIZE.saturating_sub(self.outbound_buffer.len()));
+                self.outbound_buffer.extend_from_slice(&data[..length]);
+                Ok(length)
+            },
+            _ => Err("[ERROR]: connection does not exist".to_string()),
+        }
+    }
+
+    //emits at most one segment of whatever is owed to the peer: queued data that fits in its
+    //window, our FIN, or a bare ACK. Callers keep calling until it returns 0.
+    pub fn write_next_segment(&mut self, now: Instant, outbound_buffer: &mut [u8]) -> usize {
+        if self.retransmission_due {
+            self.retransmission_due = false;
+            let delivery = self.delivery.on_send((self.send.next - self.send.unacknowledged) as usize, now);
+            if let Some(segment) = self.retransmission_queue.retransmit_front(delivery, now) {
+                return self.write_retransmission(&segment, outbound_buffer);
+            }
+        }
+
+        //the first retransmission of a recovery doesn't wait for the congestion window
+        if self.fast_retransmit_due {
+            self.fast_retransmit_due = false;
+            let delivery = self.delivery.on_send((self.send.next - self.send.unacknowledged) as usize, now);
+            if let Some(segment) = self.retransmission_queue.retransmit_next_lost(delivery, now) {
+                return self.write_retransmission(&segment, outbound_buffer);
+            }
+        }
+
+        if self.tail_loss_probe_due {
+            self.tail_loss_probe_due = false;
+            let length = self.write_tail_loss_probe(now, outbound_buffer);
+            if length > 0 {
+                return length;
+            }
+        }
+
+        if self.window_probe_due {
+            self.window_probe_due = false;
+            return self.write_window_probe(outbound_buffer);
+        }
+
+        //the same segment as a window probe, the peer has to answer it with an ACK
+        if self.keepalive_probe_due {
+            self.keepalive_probe_due = false;
+            return self.write_segment(FLAG_ACK, self.send.unacknowledged - 1, self.receive.next, &[], outbound_buffer);
+        }
+
+        if !self.pacing_allows(now) {
+            return self.write_pending_acknowledgement(outbound_buffer);
+        }
+
+        //RFC 6675 section 5 step (C), holes go out while cwnd - pipe leaves room for a segment
+        if self.recovery_point.is_some() && self.congestion_window_available() >= self.maximum_segment_size {
+            let delivery = self.delivery.on_send((self.send.next - self.send.unacknowledged) as usize, now);
+            if let Some(segment) = self.retransmission_queue.retransmit_next_lost(delivery, now) {
+                self.record_paced(segment.length as usize, now);
+                return self.write_retransmission(&segment, outbound_buffer);
+            }
+        }
+
+        let can_send_data = !self.fin_sent && matches!(
+            self.connection_state,
+            ConnectionState::Established
+                | ConnectionState::CloseWait
+                | ConnectionState::FinWait1
+                | ConnectionState::LastAck
+        );
+        if !can_send_data {
+            return self.write_pending_acknowledgement(outbound_buffer);
+        }
+
+        let unsent_offset = (self.send.next - self.send_buffer_start) as usize;
+        let unsent = self.outbound_buffer.len().saturating_sub(unsent_offset);
+        let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+        let usable_window = (self.send.window as usize).saturating_sub(in_flight).min(self.congestion_window_available());
+        //options come out of the MSS, RFC 6691
+        let maximum_payload = self.maximum_segment_size.saturating_sub(options_length(&self.outbound_options(FLAG_ACK)));
+        let segment_size = unsent.min(usable_window).min(maximum_payload);
+
+        //a partial segment made of everything left to send may have to wait for more
+        if segment_size > 0 && segment_size < maximum_payload && segment_size == unsent && self.holds_back_partial_segment(in_flight, now) {
+            return self.write_pending_acknowledgement(outbound_buffer);
+        }
+
+        if segment_size > 0 {
+            let length = self.write_new_data(segment_size, now, outbound_buffer);
+            self.record_paced(segment_size, now);
+            self.schedule_tail_loss_probe(now);
+            return length;
+        }
+
+        //the peer's window is shut and nothing is in flight for an ACK to reopen it, so probe it
+        if unsent > 0 && self.send.window == 0 && in_flight == 0 && !self.timers.is_armed(TimerKind::Persist) {
+            self.arm_persist_timer(now);
+        }
+
+        //out of data with room left in the window, rate samples from here on understate the path
+        if unsent == 0 && in_flight < self.congestion_control.cwnd() {
+            self.delivery.on_application_limited(in_flight);
+        }
+
+        if unsent == 0 && self.fin_queued {
+            self.acknowledgement_pending = false;
+            return self.write_fin(now, outbound_buffer);
+        }
+
+        self.write_pending_acknowledgement(outbound_buffer)
+    }
+
+    //RFC 9293 section 3.8.6.1, the wait before the next window probe: the RTO, doubled for every
+    //probe that didn't get the window open
+    fn arm_persist_timer(&mut self, now: Instant) {
+        let timeout = self.rto_estimator.rto()
+            .saturating_mul(1 << self.persist_backoff.min(16))
+            .min(MAXIMUM_PERSIST_TIMEOUT);
+        self.timers.arm(TimerKind::Persist, now + timeout);
+    }
+
+    //a window probe carries no data and a sequence number the peer has already had, so it is
+    //always unacceptable and always answered with an ACK carrying the current window. Unlike a
+    //probe with a byte of new data it uses no sequence space and so never needs retransmitting.
+    fn write_window_probe(&mut self, outbound_buffer: &mut [u8]) -> usize {
+        println!("[INFO]: probing the zero window of {}:{}", self.socket_pair.src_ip, self.socket_pair.src_port);
+        self.persist_backoff += 1;
+        self.write_segment(FLAG_ACK, self.send.unacknowledged - 1, self.receive.next, &[], outbound_buffer)
+    }
+
+    //RFC 1122 section 4.2.3.4, Nagle's algorithm: while anything is unacknowledged, small writes
+    //are collected until a full segment's worth has built up or the ACK comes back. A cork holds
+    //them back regardless, for up to CORK_TIMEOUT. Closing sends whatever is left either way.
+    fn holds_back_partial_segment(&mut self, in_flight: usize, now: Instant) -> bool {
+        if self.fin_queued {
+            return false;
+        }
+        if self.corked {
+            if self.cork_expired {
+                self.cork_expired = false;
+                return false;
+            }
+            if !self.timers.is_armed(TimerKind::Cork) {
+                self.timers.arm(TimerKind::Cork, now + CORK_TIMEOUT);
+            }
+            return true;
+        }
+        !self.no_delay && in_flight > 0
+    }
+
+    //the next segment_size bytes of the outbound buffer that haven't been sent yet
+    fn write_new_data(&mut self, segment_size: usize, now: Instant, outbound_buffer: &mut [u8]) -> usize {
+        let unsent_offset = (self.send.next - self.send_buffer_start) as usize;
+        let mut flags = FLAG_ACK;
+        if unsent_offset + segment_size == self.outbound_buffer.len() {
+            flags |= FLAG_PSH;
+        }
+        let payload = self.outbound_buffer[unsent_offset..unsent_offset + segment_size].to_vec();
+        let length = self.write_segment(flags, self.send.next, self.receive.next, &payload, outbound_buffer);
+        self.record_sent(self.send.next, segment_size as u32, false, false, now);
+        self.send.next += segment_size as u32;
+        self.acknowledgement_pending = false;
+        length
+    }
+
+    //RFC 8985 section 7.3, new data if the peer's window has room for it, otherwise the last
+    //segment again. Either way its ACK, or SACK, tells RACK whether anything before it is missing.
+    fn write_tail_loss_probe(&mut self, now: Instant, outbound_buffer: &mut [u8]) -> usize {
+        let unsent_offset = (self.send.next - self.send_buffer_start) as usize;
+        let unsent = self.outbound_buffer.len().saturating_sub(unsent_offset);
+        let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+        let usable_window = (self.send.window as usize).saturating_sub(in_flight);
+        let maximum_payload = self.maximum_segment_size.saturating_sub(options_length(&self.outbound_options(FLAG_ACK)));
+        let segment_size = unsent.min(usable_window).min(maximum_payload);
+
+        let length = if segment_size > 0 && !self.fin_sent {
+            self.tail_loss_probe_retransmitted = false;
+            self.write_new_data(segment_size, now, outbound_buffer)
+        } else {
+            let delivery = self.delivery.on_send(in_flight, now);
+            let Some(segment) = self.retransmission_queue.retransmit_back(delivery, now) else {
+                return 0;
+            };
+            self.tail_loss_probe_retransmitted = true;
+            self.write_retransmission(&segment, outbound_buffer)
+        };
+        println!("[INFO]: sent a tail loss probe to {}:{}", self.socket_pair.src_ip, self.socket_pair.src_port);
+        self.tail_loss_probe_end = Some(self.send.next);
+        self.timers.arm(TimerKind::Retransmission, now + self.rto_estimator.rto());
+        length
+    }
+
+    //section 7.2, a probe goes out if nothing has been heard for two RTTs, well before the RTO would
+    //fire. Only with SACK, outside recovery and with no other probe outstanding.
+    fn schedule_tail_loss_probe(&mut self, now: Instant) {
+        if !self.sack_permitted
+            || self.recovery_point.is_some()
+            || self.tail_loss_probe_end.is_some()
+            || self.retransmission_queue.is_empty() {
+            self.timers.cancel(TimerKind::TailLossProbe);
+            return;
+        }
+        let probe_timeout = match self.rto_estimator.smoothed_rtt() {
+            Some(smoothed_rtt) => {
+                let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+                if in_flight <= self.maximum_segment_size {
+                    //a lone segment may be sitting in the peer's delayed ACK timer
+                    (smoothed_rtt * 2).max(smoothed_rtt + WORST_CASE_DELAYED_ACK)
+                } else {
+                    smoothed_rtt * 2
                 }
+            },
+            None => INITIAL_PROBE_TIMEOUT,
+        };
+        self.timers.arm(TimerKind::TailLossProbe, now + probe_timeout.min(self.rto_estimator.rto()));
+    }
 
-                if !incoming_tcpheader.acknowledgement_number() == self.server_sequence_number + 1 {
-                    return Err("[ERROR]: expected client to acknowledge our server ISN + 1".to_string());
+    //RFC 8985 section 6.2 step 5, runs RACK over the scoreboard and arms the reordering timer for
+    //whatever isn't lost yet but will be if nothing turns up for it
+    fn detect_losses(&mut self, now: Instant) {
+        if !self.sack_permitted {
+            return;
+        }
+        let (_, timeout) = self.retransmission_queue.detect_losses(self.recovery_point.is_some(), self.rto_estimator.smoothed_rtt(), now);
+        match timeout {
+            Some(timeout) => self.timers.arm(TimerKind::ReorderTimeout, now + timeout),
+            None => self.timers.cancel(TimerKind::ReorderTimeout),
+        }
+    }
+
+    //RFC 2883, a first SACK block below the cumulative ACK or inside the second block reports a
+    //segment that arrived twice
+    fn has_dsack(incoming_tcpheader: &Tcp) -> bool {
+        let blocks: Vec<(SeqNum, SeqNum)> = incoming_tcpheader.options().iter()
+            .filter_map(|option| match option {
+                TcpOption::Sack(blocks) => Some(blocks),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect();
+        match blocks.as_slice() {
+            [(_, right), ..] if right.le(incoming_tcpheader.acknowledgement_number()) => true,
+            [(left, right), (second_left, second_right), ..] => second_left.le(*left) && right.le(*second_right),
+            _ => false,
+        }
+    }
+
+    //section 7.4, once the probe's ACK is in, a probe that resent the last segment and drew no
+    //D-SACK repaired a real loss, which gets the congestion response a recovery would have
+    fn update_tail_loss_probe(&mut self, dsack: bool, now: Instant) {
+        let Some(probe_end) = self.tail_loss_probe_end else {
+            return;
+        };
+        if dsack && self.tail_loss_probe_retransmitted {
+            self.tail_loss_probe_end = None;
+            return;
+        }
+        if self.send.unacknowledged.gt(probe_end) {
+            if self.tail_loss_probe_retransmitted {
+                println!("[INFO]: tail loss probe to {}:{} repaired a loss", self.socket_pair.src_ip, self.socket_pair.src_port);
+                let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+                self.congestion_control.on_loss(in_flight, now);
+                self.congestion_control.on_recovery_end(in_flight, now);
+            }
+            self.tail_loss_probe_end = None;
+        }
+    }
+
+    //with a pacing rate set, segments are spaced out by their size over the rate rather than sent
+    //back to back. The pacing timer wakes us up once the next one is due.
+    fn pacing_allows(&mut self, now: Instant) -> bool {
+        if self.congestion_control.pacing_rate().is_none() {
+            return true;
+        }
+        match self.pacing_next_send {
+            Some(next_send) if next_send > now => {
+                if !self.timers.is_armed(TimerKind::Pacing) {
+                    self.timers.arm(TimerKind::Pacing, next_send);
                 }
+                false
+            },
+            _ => true,
+        }
+    }
 
-                if !incoming_tcpheader.sequence_number() == self.server_acknowledgement_number {
-                    return Err("[ERROR]: Client did not update sequence number to matched what we acknowledged in our synack response".to_string());
+    fn record_paced(&mut self, length: usize, now: Instant) {
+        let Some(pacing_rate) = self.congestion_control.pacing_rate().filter(|rate| *rate > 0) else {
+            return;
+        };
+        //the timer wheel can wake us up to a tick late, that much lag is caught up on straight away.
+        //Any more and an idle sender would build up credit to burst with later.
+        let start = self.pacing_next_send.map_or(now, |next_send| next_send.max(now - TICK));
+        self.pacing_next_send = Some(start + Duration::from_secs_f64(length as f64 / pacing_rate as f64));
+    }
+
+    //rebuild a segment from the retransmission queue, its data is still in the outbound buffer
+    fn write_retransmission(&mut self, segment: &SentSegment, outbound_buffer: &mut [u8]) -> usize {
+        println!("[INFO]: retransmitting sequence number {} ({} bytes) to {}:{}",
+            segment.sequence_number, segment.length, self.socket_pair.src_ip, self.socket_pair.src_port);
+        if segment.syn {
+            if matches!(self.connection_state, ConnectionState::SynSent) {
+                return self.write_segment(FLAG_SYN, segment.sequence_number, SeqNum::default(), &[], outbound_buffer);
+            }
+            return self.write_syn_ack(outbound_buffer);
+        }
+        if segment.fin {
+            return self.write_segment(FLAG_FIN | FLAG_ACK, segment.sequence_number, self.receive.next, &[], outbound_buffer);
+        }
+        //options may have grown since it was first sent, whatever no longer fits goes out once
+        //the front of it has been acknowledged
+        let maximum_payload = self.maximum_segment_size.saturating_sub(options_length(&self.outbound_options(FLAG_ACK)));
+        let offset = (segment.sequence_number - self.send_buffer_start) as usize;
+        let end = (offset + (segment.length as usize).min(maximum_payload)).min(self.outbound_buffer.len());
+        let payload = self.outbound_buffer[offset.min(end)..end].to_vec();
+        self.write_segment(FLAG_ACK | FLAG_PSH, segment.sequence_number, self.receive.next, &payload, outbound_buffer)
+    }
+
+    //RFC 6298 section 5.1, the timer is started by the first segment sent while it isn't running
+    fn record_sent(&mut self, sequence_number: SeqNum, length: u32, syn: bool, fin: bool, now: Instant) {
+        let delivery = self.delivery.on_send((self.send.next - self.send.unacknowledged) as usize, now);
+        self.retransmission_queue.push(sequence_number, length, syn, fin, delivery, now);
+        if !self.timers.is_armed(TimerKind::Retransmission) {
+            self.timers.arm(TimerKind::Retransmission, now + self.rto_estimator.rto());
+        }
+    }
+
+    //sections 5.2 and 5.3, stop the timer once everything is acknowledged, otherwise restart it.
+    //Send times give the finer RTT sample, our timestamps only tick once a millisecond, so the
+    //echoed TSval is only measured when Karn's algorithm rules the acknowledged segments out
+    fn acknowledge_sent(&mut self, acknowledgement_number: SeqNum, timestamp_echo_reply: Option<u32>, now: Instant) -> Option<Duration> {
+        let queue_sample = self.retransmission_queue.acknowledge(acknowledgement_number, &mut self.delivery, now);
+        let timestamp_sample = timestamp_echo_reply.and_then(|echo_reply| self.timestamps.rtt_sample(echo_reply, now));
+        let rtt_sample = queue_sample.or(timestamp_sample);
+        if let Some(rtt) = rtt_sample {
+            self.rto_estimator.add_sample(rtt);
+        }
+        if self.retransmission_queue.is_empty() {
+            self.timers.cancel(TimerKind::Retransmission);
+        } else {
+            self.timers.arm(TimerKind::Retransmission, now + self.rto_estimator.rto());
+        }
+        rtt_sample
+    }
+
+    //how much more the congestion window lets us put in the network. Outside of recovery that is
+    //cwnd less what is in flight, during it the RFC 6675 pipe estimate stands in for the latter.
+    fn congestion_window_available(&self) -> usize {
+        let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+        let outstanding = match self.recovery_point {
+            Some(_) if self.sack_permitted => self.retransmission_queue.pipe(),
+            //without SACK each duplicate ACK stands for a segment that has left the network, the
+            //same thing RFC 6582's window inflation accounts for
+            Some(_) => in_flight.saturating_sub(self.duplicate_acknowledgements as usize * self.maximum_segment_size),
+            None => in_flight,
+        };
+        self.congestion_control.cwnd().saturating_sub(outstanding)
+    }
+
+    //RFC 5681 section 2: acknowledges nothing new while data is outstanding, carries no data, SYN
+    //or FIN and leaves the window alone
+    fn is_duplicate_acknowledgement(&self, incoming_tcpheader: &Tcp, payload: &[u8]) -> bool {
+        matches!(self.connection_state, ConnectionState::Established | ConnectionState::CloseWait)
+            && self.send.unacknowledged != self.send.next
+            && payload.is_empty()
+            && !incoming_tcpheader.is_syn_set()
+            && !incoming_tcpheader.is_fin_set()
+            && incoming_tcpheader.acknowledgement_number() == self.send.unacknowledged
+            && (incoming_tcpheader.window_size() as u32) << self.send.window_shift == self.send.window
+    }
+
+    //marks whatever the peer's SACK blocks cover on the scoreboard. Blocks have to sit inside what
+    //is outstanding, anything else is a D-SACK or bogus and is ignored.
+    fn process_sack_blocks(&mut self, incoming_tcpheader: &Tcp, now: Instant) -> bool {
+        if !self.sack_permitted {
+            return false;
+        }
+        let blocks: Vec<(SeqNum, SeqNum)> = incoming_tcpheader.options().iter()
+            .filter_map(|option| match option {
+                TcpOption::Sack(blocks) => Some(blocks),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .filter(|(left, right)| {
+                left.lt(*right) && self.send.unacknowledged.lt(*right) && right.le(self.send.next)
+            })
+            .collect();
+        !blocks.is_empty() && self.retransmission_queue.apply_sack(&blocks, &mut self.delivery, now)
+    }
+
+    //recovery starts once something is known lost and ends when everything outstanding at that
+    //point has been cumulatively acknowledged. With SACK, RACK (RFC 8985) decides what is lost,
+    //without it the third duplicate ACK does (RFC 5681 section 3.2).
+    fn update_loss_recovery(&mut self, newly_acknowledged: bool, now: Instant) {
+        let in_flight = (self.send.next - self.send.unacknowledged) as usize;
+        match self.recovery_point {
+            Some(recovery_point) if self.send.unacknowledged.ge(recovery_point) => {
+                self.recovery_point = None;
+                self.duplicate_acknowledgements = 0;
+                self.retransmission_queue.rack_mut().on_recovery_end();
+                self.congestion_control.on_recovery_end(in_flight, now);
+            },
+            Some(_) => {
+                //RFC 6582 section 3.2 step 3, a partial ACK means the next segment was lost too.
+                //With SACK, RACK finds the holes instead.
+                if newly_acknowledged && !self.sack_permitted {
+                    self.retransmission_queue.mark_front_lost();
+                    self.fast_retransmit_due = true;
+                }
+            },
+            None => {
+                let loss_detected = if self.sack_permitted {
+                    self.retransmission_queue.has_lost()
+                } else if self.duplicate_acknowledgements >= DUPLICATE_THRESHOLD as u32 {
+                    self.retransmission_queue.mark_front_lost();
+                    true
+                } else {
+                    false
+                };
+                if loss_detected {
+                    println!("[INFO]: entering loss recovery for {}:{} at sequence number {}",
+                        self.socket_pair.src_ip, self.socket_pair.src_port, self.send.unacknowledged);
+                    self.recovery_point = Some(self.send.next);
+                    self.fast_retransmit_due = true;
+                    self.tail_loss_probe_end = None;
+                    self.timers.cancel(TimerKind::TailLossProbe);
+                    self.congestion_control.on_loss(in_flight, now);
                 }
+            }
+        }
+    }
+
+    fn write_pending_acknowledgement(&mut self, outbound_buffer: &mut [u8]) -> usize {
+        if !self.acknowledgement_pending {
+            return 0;
+        }
+        self.acknowledgement_pending = false;
+        self.write_ack(outbound_buffer)
+    }
+
+    pub fn owner(&self) -> Option<u32> {
+        self.owner
+    }
+
+    //a passive connection gets its owner once it is accepted
+    pub fn set_owner(&mut self, socket_id: u32) {
+        self.owner = Some(socket_id);
+    }
+
+    pub fn take_events(&mut self) -> Vec<ConnectionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    //active open, emits our SYN and moves to SynSent
+    pub fn open(&mut self, now: Instant, outbound_buffer: &mut [u8]) -> Result<usize, String> {
+        if !matches!(self.connection_state, ConnectionState::Closed) {
+            return Err("[ERROR]: connection already exists".to_string());
+        }
+        self.send.unacknowledged = self.send.initial_sequence_number;
+        self.send.next = self.send.initial_sequence_number + 1;
+        self.send_buffer_start = self.send.next;
+        self.timers.arm(TimerKind::Connect, now + CONNECT_TIMEOUT);
+        self.connection_state = ConnectionState::SynSent;
+        self.record_sent(self.send.initial_sequence_number, 1, true, false, now);
+        Ok(self.write_segment(FLAG_SYN, self.send.initial_sequence_number, SeqNum::default(), &[], outbound_buffer))
+    }
+
+    //timers armed or cancelled since the stack last looked, for it to mirror into its wheel
+    pub fn take_timer_changes(&mut self) -> Vec<(TimerKind, Option<Instant>)> {
+        self.timers.take_changes()
+    }
+
+    //the stack's wheel says one of our timers is up
+    pub fn on_timer(&mut self, kind: TimerKind, now: Instant) {
+        if !self.timers.expire(kind, now) {
+            return;
+        }
+        match kind {
+            TimerKind::Connect => {
+                if matches!(self.connection_state, ConnectionState::SynSent | ConnectionState::SynReceived) {
+                    println!("[INFO]: connection attempt to {}:{} timed out", self.socket_pair.src_ip, self.socket_pair.src_port);
+                    self.abort_with(ConnectionEvent::TimedOut);
+                }
+            },
+            TimerKind::Retransmission => self.on_retransmission_timeout(now),
+            //nothing to do here, the stack flushes every connection after running timers
+            TimerKind::Pacing => {},
+            TimerKind::Cork => self.cork_expired = self.corked,
+            TimerKind::Keepalive => self.on_keepalive_timeout(now),
+            TimerKind::TimeWait => {
+                println!("[INFO]: TIME_WAIT over for {}:{}", self.socket_pair.src_ip, self.socket_pair.src_port);
+                self.connection_state = ConnectionState::Closed;
+            },
+            //the timer is armed again on the next flush if the window is still shut
+            TimerKind::Persist => self.window_probe_due = self.send.window == 0,
+            TimerKind::DelayedAcknowledgement => {
+                if self.received_since_acknowledgement > 0 {
+                    self.acknowledgement_pending = true;
+                }
+            },
+            TimerKind::ReorderTimeout => {
+                self.detect_losses(now);
+                self.update_loss_recovery(false, now);
+            },
+            TimerKind::TailLossProbe => {
+                if self.recovery_point.is_none() && self.tail_loss_probe_end.is_none() && !self.retransmission_queue.is_empty() {
+                    self.tail_loss_probe_due = true;
+                }
+            },
+        }
+    }
+
+    fn keepalive_applies(&self) -> bool {
+        self.keepalive && matches!(
+            self.connection_state,
+            ConnectionState::Established | ConnectionState::CloseWait | ConnectionState::FinWait2
+        )
+    }
+
+    //something was heard from the peer, or keepalive settings changed, the idle period starts over
+    fn restart_keepalive(&mut self, now: Instant) {
+        self.keepalive_probes_sent = 0;
+        if self.keepalive_applies() {
+            self.timers.arm(TimerKind::Keepalive, now + self.keepalive_idle);
+        } else {
+            self.timers.cancel(TimerKind::Keepalive);
+        }
+    }
+
+    fn on_keepalive_timeout(&mut self, now: Instant) {
+        if !self.keepalive_applies() {
+            return;
+        }
+        //with data outstanding the retransmission timer finds out soon enough if the peer is gone
+        if !self.retransmission_queue.is_empty() {
+            self.timers.arm(TimerKind::Keepalive, now + self.keepalive_idle);
+            return;
+        }
+        if self.keepalive_probes_sent >= self.keepalive_count {
+            println!("[INFO]: {}:{} did not answer {} keepalive probes, dropping the connection",
+                self.socket_pair.src_ip, self.socket_pair.src_port, self.keepalive_probes_sent);
+            self.abort_with(ConnectionEvent::TimedOut);
+            return;
+        }
+        self.keepalive_probes_sent += 1;
+        self.keepalive_probe_due = true;
+        self.timers.arm(TimerKind::Keepalive, now + self.keepalive_interval);
+    }
+
+    fn on_retransmission_timeout(&mut self, now: Instant) {
+        let Some(segment) = self.retransmission_queue.front() else {
+            return;
+        };
+        let maximum_retransmissions = if segment.syn { MAXIMUM_SYN_RETRANSMISSIONS } else { MAXIMUM_RETRANSMISSIONS };
+        if segment.transmissions > maximum_retransmissions {
+            println!("[INFO]: giving up on {}:{} after {} retransmissions",
+                self.socket_pair.src_ip, self.socket_pair.src_port, maximum_retransmissions);
+            self.abort_with(ConnectionEvent::TimedOut);
+            return;
+        }
+        //RFC 6298 section 5.4 to 5.6: resend the earliest segment, back off and restart the timer
+        self.retransmission_due = true;
+        self.fast_retransmit_due = false;
+        self.tail_loss_probe_due = false;
+        self.tail_loss_probe_end = None;
+        self.duplicate_acknowledgements = 0;
+        self.timers.cancel(TimerKind::TailLossProbe);
+        self.timers.cancel(TimerKind::ReorderTimeout);
+        //RFC 8985 section 6.3, whatever has been out too long is marked lost and resent as ACKs come back
+        self.retransmission_queue.mark_losses_on_timeout(self.rto_estimator.smoothed_rtt(), now);
+        //RFC 5681 section 3.1, ssthresh is worked out from what was in flight when the timer went off
+        self.congestion_control.on_rto((self.send.next - self.send.unacknowledged) as usize, now);
+        self.recovery_point = Some(self.send.next);
+        self.rto_estimator.back_off();
+        self.timers.arm(TimerKind::Retransmission, now + self.rto_estimator.rto());
+    }
+
+    //drop the connection without telling the peer
+    fn abort_with(&mut self, event: ConnectionEvent) {
+        self.connection_state = ConnectionState::Closed;
+        self.aborted = true;
+        self.timers.cancel_all();
+        self.retransmission_queue.clear();
+        self.reassembly_queue.clear();
+        self.events.push(event);
+    }
+
+    //abort and let the peer know, for queued connections nobody is going to accept any more
+    pub fn reset(&mut self, outbound_buffer: &mut [u8]) -> usize {
+        let length = self.write_segment(FLAG_RST, self.send.next, SeqNum::default(), &[], outbound_buffer);
+        self.abort_with(ConnectionEvent::Reset);
+        length
+    }
+
+    pub fn process_incoming(&mut self, now: Instant, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> Result<usize, String> {
+        match self.connection_state {
+            ConnectionState::Closed => {
+                //anything other than a reset hitting a closed connection gets a reset back
+                Ok(Self::write_reset_for_unexpected_segment(self.socket_pair, incoming_tcpheader, payload, outbound_buffer))
+            },
+            ConnectionState::Listen => self.process_listen(now, incoming_tcpheader, payload, outbound_buffer),
+            ConnectionState::SynSent => self.process_syn_sent(now, incoming_tcpheader, outbound_buffer),
+            ConnectionState::SynReceived
+            | ConnectionState::Established
+            | ConnectionState::FinWait1
+            | ConnectionState::FinWait2
+            | ConnectionState::CloseWait
+            | ConnectionState::Closing
+            | ConnectionState::LastAck => self.process_synchronized(now, incoming_tcpheader, payload, outbound_buffer),
+            ConnectionState::TimeWait => Ok(self.process_time_wait(now, incoming_tcpheader, payload, outbound_buffer)),
+        }
+    }
+
+    //RFC 1122 section 4.2.2.13 and RFC 6191, a SYN may reopen a connection sitting in TIME_WAIT
+    //as long as it can't be mistaken for part of the old one: its timestamp is newer than any the
+    //old connection saw or, without timestamps, its sequence number lies beyond everything received
+    pub fn allows_reuse(&self, incoming_tcpheader: &Tcp) -> bool {
+        if !matches!(self.connection_state, ConnectionState::TimeWait)
+            || !incoming_tcpheader.is_syn_set()
+            || incoming_tcpheader.is_ack_set()
+            || incoming_tcpheader.is_rst_set() {
+            return false;
+        }
+        match Self::timestamps_of(incoming_tcpheader) {
+            Some((peer_value, _)) if self.timestamps.enabled() => self.timestamps.is_newer(peer_value),
+            _ => incoming_tcpheader.sequence_number().gt(self.receive.next),
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.connection_state = ConnectionState::TimeWait;
+        self.timers.cancel_all();
+        self.retransmission_queue.clear();
+        self.timers.arm(TimerKind::TimeWait, now + self.time_wait_duration);
+    }
+
+    //all the peer can legitimately send now is its FIN again, when our ACK for it got lost. That
+    //is acknowledged again and the 2MSL wait starts over, anything else only ever gets an ACK.
+    fn process_time_wait(&mut self, now: Instant, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> usize {
+        //RFC 1337, a reset must not cut TIME_WAIT short or old duplicates could end up in the
+        //connection that reuses this pair next
+        if incoming_tcpheader.is_rst_set() {
+            println!("[INFO]: ignoring reset for {}:{} in TIME_WAIT", self.socket_pair.src_ip, self.socket_pair.src_port);
+            return 0;
+        }
+        if incoming_tcpheader.is_fin_set() {
+            self.timers.arm(TimerKind::TimeWait, now + self.time_wait_duration);
+            return self.write_ack(outbound_buffer);
+        }
+        if incoming_tcpheader.is_syn_set() || !payload.is_empty() {
+            return self.write_ack(outbound_buffer);
+        }
+        0
+    }
+
+    //application initiated close. Queues a FIN for states where we still owe the peer one, it
+    //goes out behind any data still sitting in the outbound buffer.
+    pub fn close(&mut self) -> Result<(), String> {
+        match self.connection_state {
+            ConnectionState::Listen | ConnectionState::SynSent => {
+                //nothing may go out for it any more, not even a SYN already due for retransmission
+                self.connection_state = ConnectionState::Closed;
+                self.timers.cancel_all();
+                self.retransmission_queue.clear();
+                self.reassembly_queue.clear();
+                Ok(())
+            },
+            ConnectionState::SynReceived | ConnectionState::Established => {
+                self.connection_state = ConnectionState::FinWait1;
+                self.fin_queued = true;
+                Ok(())
+            },
+            ConnectionState::CloseWait => {
+                self.connection_state = ConnectionState::LastAck;
+                self.fin_queued = true;
+                Ok(())
+            },
+            ConnectionState::FinWait1
+            | ConnectionState::FinWait2
+            | ConnectionState::Closing
+            | ConnectionState::LastAck
+            | ConnectionState::TimeWait => Err("[ERROR]: connection closing".to_string()),
+            ConnectionState::Closed => Err("[ERROR]: connection does not exist".to_string()),
+        }
+    }
+
+    fn process_listen(&mut self, now: Instant, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> Result<usize, String> {
+        if incoming_tcpheader.is_rst_set() {
+            return Ok(0);
+        }
+
+        if incoming_tcpheader.is_ack_set() || !incoming_tcpheader.is_syn_set() {
+            self.connection_state = ConnectionState::Closed;
+            return Ok(Self::write_reset_for_unexpected_segment(self.socket_pair, incoming_tcpheader, payload, outbound_buffer));
+        }
+
+        self.receive.initial_sequence_number = incoming_tcpheader.sequence_number();
+        self.receive.next = incoming_tcpheader.sequence_number() + 1;
+        self.send.window = incoming_tcpheader.window_size() as u32;
+        self.negotiate_maximum_segment_size(incoming_tcpheader);
+        self.negotiate_window_scale(incoming_tcpheader);
+        self.sack_permitted = incoming_tcpheader.options().contains(&TcpOption::SackPermitted);
+        self.timestamps.negotiate(Self::timestamps_of(incoming_tcpheader), now);
+        self.send.unacknowledged = self.send.initial_sequence_number;
+        self.send.next = self.send.initial_sequence_number + 1;
+        self.send_buffer_start = self.send.next;
 
-                self.client_sequence_number = incoming_tcpheader.sequence_number();
-                self.client_window = incoming_tcpheader.window_size();
-                self.server_acknowledgement_number = self.client_sequence_number + 1;
-                self.server_sequence_number = self.server_sequence_number + 1;
+        self.connection_state = ConnectionState::SynReceived;
+        self.record_sent(self.send.initial_sequence_number, 1, true, false, now);
+        Ok(self.write_syn_ack(outbound_buffer))
+    }
+
+    fn process_syn_sent(&mut self, now: Instant, incoming_tcpheader: &Tcp, outbound_buffer: &mut [u8]) -> Result<usize, String> {
+        let acknowledgement_number = incoming_tcpheader.acknowledgement_number();
+
+        if incoming_tcpheader.is_ack_set()
+            && (acknowledgement_number.le(self.send.initial_sequence_number)
+                || acknowledgement_number.gt(self.send.next)) {
+            if incoming_tcpheader.is_rst_set() {
+                return Ok(0);
+            }
+            return Ok(self.write_segment(FLAG_RST, acknowledgement_number, SeqNum::default(), &[], outbound_buffer));
+        }
+
+        if incoming_tcpheader.is_rst_set() {
+            if incoming_tcpheader.is_ack_set() {
+                println!("[INFO]: connection refused by {}:{}", self.socket_pair.src_ip, self.socket_pair.src_port);
+                self.abort_with(ConnectionEvent::Reset);
+            }
+            return Ok(0);
+        }
+
+        if !incoming_tcpheader.is_syn_set() {
+            return Ok(0);
+        }
+
+        self.receive.initial_sequence_number = incoming_tcpheader.sequence_number();
+        self.receive.next = incoming_tcpheader.sequence_number() + 1;
+        self.negotiate_maximum_segment_size(incoming_tcpheader);
+        self.negotiate_window_scale(incoming_tcpheader);
+        self.sack_permitted = incoming_tcpheader.options().contains(&TcpOption::SackPermitted);
+        self.timestamps.negotiate(Self::timestamps_of(incoming_tcpheader), now);
+
+        if incoming_tcpheader.is_ack_set() {
+            self.send.unacknowledged = acknowledgement_number;
+            self.acknowledge_sent(acknowledgement_number, Self::timestamps_of(incoming_tcpheader).map(|(_, echo_reply)| echo_reply), now);
+        }
+
+        if self.send.unacknowledged.gt(self.send.initial_sequence_number) {
+            self.update_send_window(incoming_tcpheader);
+            self.set_established(now);
+            return Ok(self.write_ack(outbound_buffer));
+        }
+
+        //simultaneous open, both SYNs crossed on the wire
+        self.connection_state = ConnectionState::SynReceived;
+        Ok(self.write_syn_ack(outbound_buffer))
+    }
+
+    fn process_synchronized(&mut self, now: Instant, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> Result<usize, String> {
+        let sequence_number = incoming_tcpheader.sequence_number();
+
+        //our SYN-ACK went missing and the peer is retrying its SYN, send it again
+        if matches!(self.connection_state, ConnectionState::SynReceived)
+            && incoming_tcpheader.is_syn_set()
+            && !incoming_tcpheader.is_ack_set()
+            && sequence_number == self.receive.initial_sequence_number {
+            return Ok(self.write_syn_ack(outbound_buffer));
+        }
+
+        //RFC 7323 section 5.3, PAWS runs ahead of everything else
+        let peer_timestamps = Self::timestamps_of(incoming_tcpheader);
+        if self.timestamps.enabled() && !incoming_tcpheader.is_rst_set() {
+            match peer_timestamps {
+                Some((peer_value, _)) => {
+                    if self.timestamps.is_old_duplicate(peer_value, now) {
+                        println!("[INFO]: dropping old duplicate from {}:{} (PAWS)", self.socket_pair.src_ip, self.socket_pair.src_port);
+                        return Ok(self.write_ack(outbound_buffer));
+                    }
+                },
+                //section 3.2, once agreed every segment but a reset carries them, silently drop
+                //one that doesn't
+                None => return Ok(0),
+            }
+        }
+
+        //fourth, brought forward by RFC 5961 section 4.2: a SYN is answered with a challenge ACK
+        //whatever its sequence number. A peer that really restarted will reset us in reply.
+        if incoming_tcpheader.is_syn_set() && !incoming_tcpheader.is_rst_set() {
+            return Ok(self.write_challenge_ack(Mitigation::SynChallenged, now, outbound_buffer));
+        }
+
+        //first: is the segment acceptable at all
+        if !self.segment_is_acceptable(incoming_tcpheader, payload) {
+            if incoming_tcpheader.is_rst_set() {
+                mitigations::record(Mitigation::ResetOutOfWindow);
+                return Ok(0);
+            }
+            return Ok(self.write_ack(outbound_buffer));
+        }
+        self.restart_keepalive(now);
+
+        //RFC 7323 section 4.3, remember the timestamp of a segment that covers what we last
+        //acknowledged so it is what gets echoed
+        if let Some((peer_value, _)) = peer_timestamps {
+            if self.timestamps.enabled() {
+                self.timestamps.update_recent(peer_value, sequence_number, now);
+            }
+        }
+
+        //second: the RST bit. RFC 5961 section 3.2, only a reset exactly at rcv.nxt is believed, one
+        //merely somewhere in the window gets a challenge ACK the real peer can answer exactly
+        if incoming_tcpheader.is_rst_set() {
+            if sequence_number != self.receive.next {
+                return Ok(self.write_challenge_ack(Mitigation::ResetChallenged, now, outbound_buffer));
+            }
+            println!("[INFO]: connection reset by peer {}:{}", self.socket_pair.src_ip, self.socket_pair.src_port);
+            self.abort_with(ConnectionEvent::Reset);
+            return Ok(0);
+        }
+
+        //fifth: the ACK field
+        if !incoming_tcpheader.is_ack_set() {
+            return Ok(0);
+        }
+
+        let acknowledgement_number = incoming_tcpheader.acknowledgement_number();
+
+        if matches!(self.connection_state, ConnectionState::SynReceived) {
+            if !self.acknowledgement_is_acceptable(acknowledgement_number) {
+                return Ok(self.write_segment(FLAG_RST, acknowledgement_number, SeqNum::default(), &[], outbound_buffer));
+            }
+            self.update_send_window(incoming_tcpheader);
+            self.set_established(now);
+        }
+
+        //RFC 5961 section 5.2, an ACK for something we haven't sent yet, or from further back than
+        //the peer's largest window, can't be from the peer. The segment is dropped, data and all.
+        if acknowledgement_number.gt(self.send.next)
+            || acknowledgement_number.lt(self.send.unacknowledged - self.send.maximum_window) {
+            return Ok(self.write_challenge_ack(Mitigation::AcknowledgementOutOfRange, now, outbound_buffer));
+        }
+
+        let duplicate_acknowledgement = self.is_duplicate_acknowledgement(incoming_tcpheader, payload);
+        let bytes_in_flight = (self.send.next - self.send.unacknowledged) as usize;
+        let delivered_before = self.delivery.delivered();
+        let newly_sacked = self.process_sack_blocks(incoming_tcpheader, now);
+        let dsack = self.sack_permitted && Self::has_dsack(incoming_tcpheader);
+        if dsack {
+            self.retransmission_queue.rack_mut().on_dsack(self.send.next, self.send.unacknowledged);
+        }
+        let mut acknowledged_bytes = 0;
+        let mut rtt = None;
+        if self.send.unacknowledged.lt(acknowledgement_number) {
+            self.duplicate_acknowledgements = 0;
+            acknowledged_bytes = (acknowledgement_number - self.send.unacknowledged) as usize;
+            self.send.unacknowledged = acknowledgement_number;
+            rtt = self.acknowledge_sent(acknowledgement_number, Self::timestamps_of(incoming_tcpheader).map(|(_, echo_reply)| echo_reply), now);
+            self.release_acknowledged_data();
+        }
+        if acknowledged_bytes > 0 || newly_sacked {
+            let rate_sample = self.delivery.take_sample(rtt);
+            self.congestion_control.on_ack(&AckEvent {
+                now,
+                acknowledged_bytes,
+                delivered_bytes: (self.delivery.delivered() - delivered_before) as usize,
+                delivered: self.delivery.delivered(),
+                bytes_in_flight,
+                rtt,
+                rate_sample,
+                in_recovery: self.recovery_point.is_some(),
+            });
+        }
+        if duplicate_acknowledgement {
+            self.duplicate_acknowledgements += 1;
+        }
+        self.update_tail_loss_probe(dsack, now);
+        if acknowledged_bytes > 0 || newly_sacked {
+            self.detect_losses(now);
+        }
+        self.update_loss_recovery(acknowledged_bytes > 0, now);
+        if acknowledged_bytes > 0 {
+            self.schedule_tail_loss_probe(now);
+        }
 
-                self.connection_state = ConnectionState::Established;
-                println!("[INFO] successfully established tcp connection");
+        if self.send.unacknowledged.le(acknowledgement_number)
+            && (self.send.window_update_sequence.lt(sequence_number)
+                || (self.send.window_update_sequence == sequence_number
+                    && self.send.window_update_acknowledgement.le(acknowledgement_number))) {
+            self.update_send_window(incoming_tcpheader);
+        }
 
-                Ok(0 as usize)
+        let our_fin_acknowledged = self.fin_sent && self.send.unacknowledged == self.send.next;
 
+        match self.connection_state {
+            ConnectionState::FinWait1 if our_fin_acknowledged => {
+                self.connection_state = ConnectionState::FinWait2;
+            },
+            ConnectionState::Closing => {
+                if !our_fin_acknowledged {
+                    return Ok(0);
+                }
+                self.enter_time_wait(now);
             },
-            ConnectionState::SynSent => todo!(),
-            ConnectionState::Established => {
-                println!("Wtf is going on here...");
-                return Ok(0 as usize)
+            ConnectionState::LastAck => {
+                if our_fin_acknowledged {
+                    self.connection_state = ConnectionState::Closed;
+                }
+                return Ok(0);
             },
-            ConnectionState::FinWait1 => todo!(),
-            ConnectionState::FinWait2 => todo!(),
-            ConnectionState::CloseWait => todo!(),
-            ConnectionState::Closing => todo!(),
-            ConnectionState::LastAct => todo!(),
-            ConnectionState::TimeWait => todo!(),
-            ConnectionState::Closed => todo!(),
+            _ => {}
+        }
+
+        let mut acknowledgement_needed = false;
+
+        //seventh: the segment text
+        if !payload.is_empty() {
+            let receive_next = self.receive.next;
+            //RFC 5681 section 4.2, anything out of order or filling a gap is acknowledged at once
+            let mut acknowledge_now = !self.delayed_acknowledgement
+                || incoming_tcpheader.is_psh_set()
+                || sequence_number.gt(receive_next)
+                || !self.reassembly_queue.is_empty();
+            match self.connection_state {
+                ConnectionState::Established | ConnectionState::FinWait1 | ConnectionState::FinWait2 => {
+                    self.accept_segment_text(sequence_number, payload);
+                },
+                //the peer has already sent a FIN so this should never happen, ignore it
+                _ => {}
+            }
+            let received = (self.receive.next - receive_next) as usize;
+            //a duplicate, the peer might be probing or retransmitting blindly
+            acknowledge_now |= received == 0;
+            self.received_since_acknowledgement += received;
+            if payload.len() > self.received_segment_size {
+                self.received_segment_size = payload.len().min(LOCAL_MAXIMUM_SEGMENT_SIZE);
+            }
+            if acknowledge_now || self.received_since_acknowledgement >= 2 * self.received_segment_size {
+                acknowledgement_needed = true;
+            } else if !self.timers.is_armed(TimerKind::DelayedAcknowledgement) {
+                self.timers.arm(TimerKind::DelayedAcknowledgement, now + self.delayed_acknowledgement_timeout);
+            }
         }
-        
 
+        //eighth: the FIN bit, only once everything before it has arrived. One that turns up ahead
+        //of a gap is remembered and takes effect when the gap fills.
+        let fin_sequence_number = sequence_number + payload.len() as u32;
+        if incoming_tcpheader.is_fin_set() && fin_sequence_number.gt(self.receive.next) {
+            self.reassembly_queue.set_fin(fin_sequence_number);
+        }
+        let fin_reached = (incoming_tcpheader.is_fin_set() && fin_sequence_number == self.receive.next)
+            || self.reassembly_queue.take_fin(self.receive.next);
+        if fin_reached {
+            self.receive.next += 1;
+            acknowledgement_needed = true;
+            match self.connection_state {
+                ConnectionState::SynReceived | ConnectionState::Established => {
+                    self.connection_state = ConnectionState::CloseWait;
+                },
+                ConnectionState::FinWait1 if our_fin_acknowledged => self.enter_time_wait(now),
+                ConnectionState::FinWait1 => {
+                    self.connection_state = ConnectionState::Closing;
+                },
+                ConnectionState::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+
+        if acknowledgement_needed {
+            return Ok(self.write_ack(outbound_buffer));
+        }
+        Ok(0)
     }
-   pub fn set_intial_sequence_number () -> u32 {
-        1
-   }
 
-     
+    fn segment_is_acceptable(&self, incoming_tcpheader: &Tcp, payload: &[u8]) -> bool {
+        let segment_length = Self::segment_length(incoming_tcpheader, payload);
+        let sequence_number = incoming_tcpheader.sequence_number();
+        let window = self.receive.window;
+        let window_end = self.receive.next + window;
+
+        match (segment_length, window) {
+            (0, 0) => sequence_number == self.receive.next,
+            (0, _) => sequence_number.between(self.receive.next, window_end),
+            (_, 0) => false,
+            (_, _) => {
+                let last_sequence_number = sequence_number + (segment_length - 1);
+                sequence_number.between(self.receive.next, window_end)
+                    || last_sequence_number.between(self.receive.next, window_end)
+            }
+        }
+    }
+
+    fn set_established(&mut self, now: Instant) {
+        self.connection_state = ConnectionState::Established;
+        self.timers.cancel(TimerKind::Connect);
+        self.restart_keepalive(now);
+        self.events.push(ConnectionEvent::Established);
+        println!("[INFO] successfully established tcp connection");
+    }
+
+    fn acknowledgement_is_acceptable(&self, acknowledgement_number: SeqNum) -> bool {
+        self.send.unacknowledged.lt(acknowledgement_number)
+            && acknowledgement_number.le(self.send.next)
+    }
+
+    //in order text goes straight to the inbound buffer, anything overlapping what we have already
+    //got is trimmed. Text beyond a gap waits in the reassembly queue until the gap is filled.
+    fn accept_segment_text(&mut self, sequence_number: SeqNum, payload: &[u8]) {
+        if sequence_number.gt(self.receive.next) {
+            self.reassembly_queue.insert(self.receive.next, self.receive.window, sequence_number, payload);
+            return;
+        }
+        let already_received = (self.receive.next - sequence_number) as usize;
+        if already_received >= payload.len() {
+            return;
+        }
+        let receive_next = self.receive.next;
+        let window = self.receive.window as usize;
+        let new_text = &payload[already_received..payload.len().min(already_received + window)];
+        self.inbound_buffer.extend_from_slice(new_text);
+        self.receive.next += new_text.len() as u32;
+
+        while let Some(text) = self.reassembly_queue.pop_contiguous(self.receive.next) {
+            self.inbound_buffer.extend_from_slice(&text);
+            self.receive.next += text.len() as u32;
+        }
+        //the right edge stays put, whatever arrived comes off the window
+        self.receive.window = self.receive.window.saturating_sub(self.receive.next - receive_next);
+        self.update_receive_window();
+    }
+
+    //drop everything the peer has now acknowledged from the front of the outbound buffer
+    fn release_acknowledged_data(&mut self) {
+        let acknowledged = (self.send.unacknowledged - self.send_buffer_start) as usize;
+        let released = acknowledged.min(self.outbound_buffer.len());
+        self.outbound_buffer.drain(..released);
+        self.send_buffer_start += released as u32;
+    }
+
+    //the advertised window is whatever space is left in the inbound buffer, except that per RFC
+    //9293 section 3.8.6.2.2 it only opens up once it can do so by a full segment or half the buffer,
+    //whichever is smaller. Opening it a few bytes at a time invites the peer to send tinygrams.
+    fn update_receive_window(&mut self) {
+        let available = self.receive_buffer_size.saturating_sub(self.inbound_buffer.len()) as u32;
+        let threshold = (self.receive_buffer_size / 2).min(LOCAL_MAXIMUM_SEGMENT_SIZE) as u32;
+        if available < self.receive.window || available - self.receive.window >= threshold {
+            self.receive.window = available;
+        }
+    }
+
+    //the window field of an outbound segment. A SYN's window is never scaled, and without window
+    //scaling nothing beyond 64KiB can be advertised at all.
+    fn advertised_window(&self, flags: u8) -> u16 {
+        let shift = if flags & FLAG_SYN != 0 { 0 } else { self.receive.window_shift };
+        (self.receive.window >> shift).min(u16::MAX as u32) as u16
+    }
+
+    fn update_send_window(&mut self, incoming_tcpheader: &Tcp) {
+        //the window on a SYN is never scaled
+        let shift = if incoming_tcpheader.is_syn_set() { 0 } else { self.send.window_shift };
+        self.send.window = (incoming_tcpheader.window_size() as u32) << shift;
+        self.send.maximum_window = self.send.maximum_window.max(self.send.window);
+        self.send.window_update_sequence = incoming_tcpheader.sequence_number();
+        self.send.window_update_acknowledgement = incoming_tcpheader.acknowledgement_number();
+        if self.send.window > 0 {
+            self.persist_backoff = 0;
+            self.window_probe_due = false;
+            self.timers.cancel(TimerKind::Persist);
+        }
+    }
+
+    //RFC 9293 section 3.7.1, whatever the peer's SYN asks for, capped to what fits in our MTU
+    fn negotiate_maximum_segment_size(&mut self, incoming_tcpheader: &Tcp) {
+        let peer_maximum_segment_size = incoming_tcpheader.options().iter().find_map(|option| match option {
+            TcpOption::MaximumSegmentSize(maximum_segment_size) => Some(*maximum_segment_size as usize),
+            _ => None,
+        });
+        self.maximum_segment_size = peer_maximum_segment_size
+            .unwrap_or(DEFAULT_MAXIMUM_SEGMENT_SIZE)
+            .clamp(MINIMUM_MAXIMUM_SEGMENT_SIZE, LOCAL_MAXIMUM_SEGMENT_SIZE);
+        self.congestion_control.set_maximum_segment_size(self.maximum_segment_size);
+    }
+
+    //RFC 7323 section 2.2, scaling is only used when both SYNs carried the option. Our own shift
+    //is the smallest that lets the whole receive buffer be advertised.
+    fn negotiate_window_scale(&mut self, incoming_tcpheader: &Tcp) {
+        let peer_window_shift = incoming_tcpheader.options().iter().find_map(|option| match option {
+            TcpOption::WindowScale(shift) => Some(*shift),
+            _ => None,
+        });
+        match peer_window_shift {
+            Some(shift) => {
+                if shift > MAXIMUM_WINDOW_SHIFT {
+                    println!("[INFO]: peer {}:{} asked for a window shift of {}, using {}",
+                        self.socket_pair.src_ip, self.socket_pair.src_port, shift, MAXIMUM_WINDOW_SHIFT);
+                }
+                self.window_scaling = true;
+                self.send.window_shift = shift.min(MAXIMUM_WINDOW_SHIFT);
+                self.receive.window_shift = self.local_window_shift();
+            },
+            None => {
+                self.window_scaling = false;
+                self.send.window_shift = 0;
+                self.receive.window_shift = 0;
+            }
+        }
+    }
+
+    fn local_window_shift(&self) -> u8 {
+        Self::window_shift_for(self.receive_buffer_size)
+    }
+
+    fn window_shift_for(receive_buffer_size: usize) -> u8 {
+        let mut shift = 0;
+        while shift < MAXIMUM_WINDOW_SHIFT && (receive_buffer_size >> shift) > u16::MAX as usize {
+            shift += 1;
+        }
+        shift
+    }
+
+    //TSval and TSecr of a segment, if it carries the option
+    fn timestamps_of(incoming_tcpheader: &Tcp) -> Option<(u32, u32)> {
+        incoming_tcpheader.options().iter().find_map(|option| match option {
+            TcpOption::Timestamps { value, echo_reply } => Some((*value, *echo_reply)),
+            _ => None,
+        })
+    }
+
+    fn segment_length(incoming_tcpheader: &Tcp, payload: &[u8]) -> u32 {
+        payload.len() as u32
+            + incoming_tcpheader.is_syn_set() as u32
+            + incoming_tcpheader.is_fin_set() as u32
+    }
+
+    fn write_syn_ack(&mut self, outbound_buffer: &mut [u8]) -> usize {
+        self.write_segment(FLAG_SYN | FLAG_ACK, self.send.initial_sequence_number, self.receive.next, &[], outbound_buffer)
+    }
+
+    //RFC 5961 section 7, an ACK sent in reply to something that looks like a blind attack. They are
+    //rate limited across the whole stack.
+    fn write_challenge_ack(&mut self, mitigation: Mitigation, now: Instant, outbound_buffer: &mut [u8]) -> usize {
+        let count = mitigations::record(mitigation);
+        println!("[INFO]: {:?} from {}:{} ({} so far)", mitigation, self.socket_pair.src_ip, self.socket_pair.src_port, count);
+        if !mitigations::challenge_ack_allowed(now) {
+            return 0;
+        }
+        self.write_ack(outbound_buffer)
+    }
+
+    fn write_ack(&mut self, outbound_buffer: &mut [u8]) -> usize {
+        self.write_segment(FLAG_ACK, self.send.next, self.receive.next, &[], outbound_buffer)
+    }
+
+    fn write_fin(&mut self, now: Instant, outbound_buffer: &mut [u8]) -> usize {
+        let length = self.write_segment(FLAG_FIN | FLAG_ACK, self.send.next, self.receive.next, &[], outbound_buffer);
+        self.record_sent(self.send.next, 1, false, true, now);
+        self.send.next += 1;
+        self.fin_sent = true;
+        length
+    }
+
+    fn write_segment(&mut self, flags: u8, sequence_number: SeqNum, acknowledgement_number: SeqNum, payload: &[u8], outbound_buffer: &mut [u8]) -> usize {
+        //whatever goes out with an ACK takes care of any ACK we were holding back
+        if flags & FLAG_ACK != 0 {
+            self.received_since_acknowledgement = 0;
+            self.timers.cancel(TimerKind::DelayedAcknowledgement);
+            self.timestamps.acknowledgement_sent(acknowledgement_number);
+        }
+        let mut outbound_tcp_header = Tcp::default();
+        outbound_tcp_header.set_source_port(self.socket_pair.dest_port);
+        outbound_tcp_header.set_destination_port(self.socket_pair.src_port);
+        outbound_tcp_header.set_flags(flags);
+        outbound_tcp_header.set_sequence_number(sequence_number);
+        if flags & FLAG_ACK != 0 {
+            outbound_tcp_header.set_acknowledgement_number(acknowledgement_number);
+        }
+        if flags & FLAG_RST == 0 {
+            outbound_tcp_header.set_window(self.advertised_window(flags));
+        }
+        for option in self.outbound_options(flags) {
+            if let Err(e) = outbound_tcp_header.push_option(option) {
+                eprintln!("[ERROR]: {}", e);
+            }
+        }
+        Self::write_packet(self.socket_pair, &outbound_tcp_header, payload, outbound_buffer)
+    }
+
+    //options carried by a segment with the given flags. The MSS, window scale and SACK-permitted
+    //only ever go on a SYN, and a SYN-ACK only offers back what the peer's SYN had. Timestamps go on
+    //everything but resets once agreed, SACK blocks go last, as many as still fit.
+    fn outbound_options(&self, flags: u8) -> Vec<TcpOption> {
+        let now = Instant::now();
+        let mut options = Vec::new();
+        if flags & FLAG_SYN != 0 {
+            options.push(TcpOption::MaximumSegmentSize(LOCAL_MAXIMUM_SEGMENT_SIZE as u16));
+            if flags & FLAG_ACK == 0 {
+                options.push(TcpOption::WindowScale(self.local_window_shift()));
+                options.push(TcpOption::SackPermitted);
+                options.push(TcpOption::Timestamps { value: self.timestamps.value(now), echo_reply: 0 });
+            } else {
+                if self.window_scaling {
+                    options.push(TcpOption::WindowScale(self.receive.window_shift));
+                }
+                if self.sack_permitted {
+                    options.push(TcpOption::SackPermitted);
+                }
+                if self.timestamps.enabled() {
+                    options.push(self.timestamps.option(now));
+                }
+            }
+            return options;
+        }
+        if flags & FLAG_RST != 0 {
+            return options;
+        }
+        if self.timestamps.enabled() {
+            options.push(self.timestamps.option(now));
+        }
+        if flags & FLAG_ACK != 0 && self.sack_permitted && !self.reassembly_queue.is_empty() {
+            //two NOPs and the kind and length bytes come ahead of the blocks
+            let room = MAXIMUM_OPTIONS_LENGTH.saturating_sub(options_length(&options) + 4);
+            options.push(TcpOption::Sack(self.reassembly_queue.sack_blocks(room / 8)));
+        }
+        options
+    }
+
+    //the SYN-ACK for a SYN we keep no state for. It offers back what the SYN had, as the SYN-ACK of
+    //a real passive open would, with the cookie as our ISN and, with timestamps, the options the
+    //cookie doesn't hold tucked into the TSval.
+    pub fn write_syn_cookie_ack(socket_pair: SocketPair, syn: &Tcp, cookie: SeqNum, timestamp: u32, receive_buffer_size: usize, outbound_buffer: &mut [u8]) -> usize {
+        let mut outbound_tcp_header = Tcp::default();
+        outbound_tcp_header.set_source_port(socket_pair.dest_port);
+        outbound_tcp_header.set_destination_port(socket_pair.src_port);
+        outbound_tcp_header.set_flags(FLAG_SYN | FLAG_ACK);
+        outbound_tcp_header.set_sequence_number(cookie);
+        outbound_tcp_header.set_acknowledgement_number(syn.sequence_number() + 1);
+        outbound_tcp_header.set_window(receive_buffer_size.min(u16::MAX as usize) as u16);
+
+        let mut options = vec![TcpOption::MaximumSegmentSize(LOCAL_MAXIMUM_SEGMENT_SIZE as u16)];
+        for option in syn.options() {
+            match option {
+                TcpOption::WindowScale(_) => options.push(TcpOption::WindowScale(Self::window_shift_for(receive_buffer_size))),
+                TcpOption::SackPermitted => options.push(TcpOption::SackPermitted),
+                TcpOption::Timestamps { value, .. } => options.push(TcpOption::Timestamps { value: timestamp, echo_reply: *value }),
+                _ => {}
+            }
+        }
+        for option in options {
+            if let Err(e) = outbound_tcp_header.push_option(option) {
+                eprintln!("[ERROR]: {}", e);
+            }
+        }
+        Self::write_packet(socket_pair, &outbound_tcp_header, &[], outbound_buffer)
+    }
+
+    //a connection rebuilt from the ACK that returned a valid SYN cookie, created with the cookie as
+    //its ISN. It picks up where process_listen would have left it, with the handshake done. Any
+    //data or FIN on the ACK is for process_incoming afterwards.
+    pub fn restore_from_syn_cookie(&mut self, now: Instant, incoming_tcpheader: &Tcp, cookie: &SynCookie) {
+        self.receive.initial_sequence_number = incoming_tcpheader.sequence_number() - 1;
+        self.receive.next = incoming_tcpheader.sequence_number();
+        self.maximum_segment_size = cookie.maximum_segment_size.clamp(MINIMUM_MAXIMUM_SEGMENT_SIZE, LOCAL_MAXIMUM_SEGMENT_SIZE);
+        self.congestion_control.set_maximum_segment_size(self.maximum_segment_size);
+        if let Some(shift) = cookie.window_shift {
+            self.window_scaling = true;
+            self.send.window_shift = shift.min(MAXIMUM_WINDOW_SHIFT);
+            self.receive.window_shift = self.local_window_shift();
+        }
+        self.sack_permitted = cookie.sack_permitted;
+        self.timestamps.negotiate(Self::timestamps_of(incoming_tcpheader), now);
+        //the stateless SYN-ACK acknowledged the peer's SYN
+        self.timestamps.acknowledgement_sent(self.receive.next);
+        //our SYN was acknowledged before we ever knew about it, so it never goes in the
+        //retransmission queue and the handshake gives no RTT sample
+        self.send.unacknowledged = self.send.initial_sequence_number + 1;
+        self.send.next = self.send.unacknowledged;
+        self.send_buffer_start = self.send.next;
+        self.update_send_window(incoming_tcpheader);
+        self.set_established(now);
+    }
+
+    //RFC 9293 section 3.10.7.1, the reset sent back for a segment that belongs to no connection
+    pub fn write_reset_for_unexpected_segment(socket_pair: SocketPair, incoming_tcpheader: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> usize {
+        if incoming_tcpheader.is_rst_set() {
+            return 0;
+        }
+        let mut outbound_tcp_header = Tcp::default();
+        outbound_tcp_header.set_source_port(socket_pair.dest_port);
+        outbound_tcp_header.set_destination_port(socket_pair.src_port);
+        if incoming_tcpheader.is_ack_set() {
+            outbound_tcp_header.set_flags(FLAG_RST);
+            outbound_tcp_header.set_sequence_number(incoming_tcpheader.acknowledgement_number());
+        } else {
+            outbound_tcp_header.set_flags(FLAG_RST | FLAG_ACK);
+            outbound_tcp_header.set_acknowledgement_number(
+                incoming_tcpheader.sequence_number() + Self::segment_length(incoming_tcpheader, payload)
+            );
+        }
+        Self::write_packet(socket_pair, &outbound_tcp_header, &[], outbound_buffer)
+    }
+
+    fn write_packet(socket_pair: SocketPair, outbound_tcp_header: &Tcp, payload: &[u8], outbound_buffer: &mut [u8]) -> usize {
+        let mut outbound_ipv4_header = Ipv4::new(socket_pair.dest_ip, socket_pair.src_ip);
+        let mut serialized_tcp_header = outbound_tcp_header.serialize();
+        let pseduo_header = Tcp::create_checksum_pseudo_header(
+            socket_pair.dest_ip,
+            socket_pair.src_ip,
+            &serialized_tcp_header,
+            payload
+        );
+        let tcp_checksum = Tcp::calculate_tcp_checksum(pseduo_header.as_slice(), &serialized_tcp_header, payload);
+        Tcp::write_checksum(&mut serialized_tcp_header, tcp_checksum);
+        outbound_ipv4_header.set_total_length(20 + (serialized_tcp_header.len() + payload.len()) as u16);
+
+        //serialize ipv4 header
+        let mut serialized_ipv4_header = outbound_ipv4_header.serialize();
+        Ipv4::calculate_and_set_checksum(&mut serialized_ipv4_header);
+
+        let ipv4_header_length = serialized_ipv4_header.len();
+        let tcp_header_length = serialized_tcp_header.len();
+        let payload_starts_at = ipv4_header_length + tcp_header_length;
+        outbound_buffer[ .. ipv4_header_length ].copy_from_slice(serialized_ipv4_header.as_slice());
+        outbound_buffer[ ipv4_header_length .. payload_starts_at ].copy_from_slice(serialized_tcp_header.as_slice());
+        outbound_buffer[ payload_starts_at .. payload_starts_at + payload.len() ].copy_from_slice(payload);
+
+        outbound_ipv4_header.total_length() as usize
+    }
 
 }
 
 
 enum ConnectionState {
-    Unitialized,
     Listen,
     SynReceived,
     SynSent,
@@ -149,7 +1705,231 @@ enum ConnectionState {
     FinWait2,
     CloseWait,
     Closing,
-    LastAct,
+    LastAck,
     TimeWait,
     Closed
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+    use super::{Connection, SocketPair, LOCAL_ADDRESS};
+    use crate::seqnum::SeqNum;
+    use crate::stack::MTU;
+    use crate::tcp::{Tcp, TcpOption, FLAG_ACK, FLAG_SYN};
+    use crate::timer::TimerKind;
+
+    const LOCAL_ISN: u32 = 1000;
+    const PEER_ISN: u32 = 5000;
+    const PEER_MAXIMUM_SEGMENT_SIZE: u16 = 1000;
+    const PEER_WINDOW: u16 = 65535;
+    //ten segments, exactly the initial window at this MSS
+    const DATA_LENGTH: usize = 10_000;
+
+    fn socket_pair() -> SocketPair {
+        SocketPair {
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dest_ip: LOCAL_ADDRESS,
+            src_port: 9000,
+            dest_port: 49152,
+        }
+    }
+
+    fn segment_from_peer(flags: u8, acknowledgement_number: u32, window: u16) -> Tcp {
+        let pair = socket_pair();
+        Tcp::new(pair.src_port, pair.dest_port, SeqNum::new(PEER_ISN + 1), SeqNum::new(acknowledgement_number), 0x50, flags, window, 0, 0)
+    }
+
+    fn ack(acknowledgement_number: u32) -> Tcp {
+        segment_from_peer(FLAG_ACK, acknowledgement_number, PEER_WINDOW)
+    }
+
+    //sequence numbers of everything the connection has queued up to send right now
+    fn flush(connection: &mut Connection, now: Instant) -> Vec<u32> {
+        let mut outbound_buffer = [0u8; MTU];
+        let mut sent = Vec::new();
+        loop {
+            let length = connection.write_next_segment(now, &mut outbound_buffer);
+            if length == 0 {
+                return sent;
+            }
+            let tcp = Tcp::deserialize(&outbound_buffer[20..length]).expect("we sent a malformed segment");
+            if length > 20 + tcp.header_length_in_bytes() as usize {
+                sent.push(tcp.sequence_number().value());
+            }
+        }
+    }
+
+    //an established connection that has sent DATA_LENGTH bytes in 1000 byte segments, none of
+    //them acknowledged yet
+    fn established_with_data_in_flight() -> (Connection, Instant) {
+        let now = Instant::now();
+        let mut outbound_buffer = [0u8; MTU];
+        let mut connection = Connection::new_active(socket_pair(), 1, SeqNum::new(LOCAL_ISN));
+        connection.open(now, &mut outbound_buffer).unwrap();
+
+        let mut syn_ack = segment_from_peer(FLAG_SYN | FLAG_ACK, LOCAL_ISN + 1, PEER_WINDOW);
+        syn_ack.set_sequence_number(SeqNum::new(PEER_ISN));
+        syn_ack.push_option(TcpOption::MaximumSegmentSize(PEER_MAXIMUM_SEGMENT_SIZE)).unwrap();
+        connection.process_incoming(now, &syn_ack, &[], &mut outbound_buffer).unwrap();
+
+        connection.write(&[7u8; DATA_LENGTH]).unwrap();
+        let sent = flush(&mut connection, now);
+        assert_eq!(sent.len(), 10);
+        (connection, now)
+    }
+
+    fn receive(connection: &mut Connection, now: Instant, segment: &Tcp) {
+        let mut outbound_buffer = [0u8; MTU];
+        connection.process_incoming(now, segment, &[], &mut outbound_buffer).unwrap();
+    }
+
+    //the peer's segments are well under our MSS, every second one of them still gets an ACK
+    #[test]
+    fn every_second_full_segment_from_the_peer_is_acknowledged() {
+        let (mut connection, now) = established_with_data_in_flight();
+        let mut outbound_buffer = [0u8; MTU];
+        let payload = [1u8; PEER_MAXIMUM_SEGMENT_SIZE as usize];
+        let mut sequence_number = PEER_ISN + 1;
+        for _ in 0..2 {
+            let mut segment = ack(LOCAL_ISN + 1);
+            segment.set_sequence_number(SeqNum::new(sequence_number));
+            assert_eq!(connection.process_incoming(now, &segment, &payload, &mut outbound_buffer).unwrap(), 0);
+            assert!(connection.timers.is_armed(TimerKind::DelayedAcknowledgement));
+            sequence_number += payload.len() as u32;
+
+            let mut segment = ack(LOCAL_ISN + 1);
+            segment.set_sequence_number(SeqNum::new(sequence_number));
+            assert!(connection.process_incoming(now, &segment, &payload, &mut outbound_buffer).unwrap() > 0);
+            assert!(!connection.timers.is_armed(TimerKind::DelayedAcknowledgement));
+            sequence_number += payload.len() as u32;
+        }
+    }
+
+    //RFC 7323 section 3.2, with timestamps agreed an ACK that doesn't carry one is ignored
+    #[test]
+    fn segments_without_timestamps_are_dropped_once_negotiated() {
+        let now = Instant::now();
+        let mut outbound_buffer = [0u8; MTU];
+        let mut connection = Connection::new_active(socket_pair(), 1, SeqNum::new(LOCAL_ISN));
+        connection.open(now, &mut outbound_buffer).unwrap();
+        let mut syn_ack = segment_from_peer(FLAG_SYN | FLAG_ACK, LOCAL_ISN + 1, PEER_WINDOW);
+        syn_ack.set_sequence_number(SeqNum::new(PEER_ISN));
+        syn_ack.push_option(TcpOption::Timestamps { value: 1, echo_reply: 0 }).unwrap();
+        connection.process_incoming(now, &syn_ack, &[], &mut outbound_buffer).unwrap();
+        connection.write(&[7u8; 100]).unwrap();
+        assert_eq!(flush(&mut connection, now).len(), 1);
+
+        receive(&mut connection, now, &ack(LOCAL_ISN + 101));
+        assert_eq!(connection.send.unacknowledged, SeqNum::new(LOCAL_ISN + 1));
+
+        let mut timestamped_ack = ack(LOCAL_ISN + 101);
+        timestamped_ack.push_option(TcpOption::Timestamps { value: 2, echo_reply: 0 }).unwrap();
+        receive(&mut connection, now, &timestamped_ack);
+        assert_eq!(connection.send.unacknowledged, SeqNum::new(LOCAL_ISN + 101));
+    }
+
+    #[test]
+    fn closing_during_syn_sent_sends_nothing_more() {
+        let now = Instant::now();
+        let mut outbound_buffer = [0u8; MTU];
+        let mut connection = Connection::new_active(socket_pair(), 1, SeqNum::new(LOCAL_ISN));
+        connection.open(now, &mut outbound_buffer).unwrap();
+        connection.close().unwrap();
+        assert!(connection.is_closed());
+
+        //whatever was armed before the close must not bring the SYN back
+        let later = now + Duration::from_secs(10);
+        for kind in TimerKind::ALL {
+            connection.on_timer(kind, later);
+        }
+        assert_eq!(connection.write_next_segment(later, &mut outbound_buffer), 0);
+    }
+
+    #[test]
+    fn third_duplicate_ack_triggers_fast_retransmit() {
+        let (mut connection, now) = established_with_data_in_flight();
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1001));
+        flush(&mut connection, now);
+
+        for _ in 0..2 {
+            receive(&mut connection, now, &ack(LOCAL_ISN + 1001));
+            assert!(connection.recovery_point.is_none());
+            assert!(flush(&mut connection, now).is_empty());
+        }
+
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1001));
+        assert!(connection.recovery_point.is_some());
+        assert_eq!(flush(&mut connection, now).first(), Some(&(LOCAL_ISN + 1001)));
+    }
+
+    #[test]
+    fn entering_fast_recovery_halves_the_window() {
+        let (mut connection, now) = established_with_data_in_flight();
+        for _ in 0..4 {
+            receive(&mut connection, now, &ack(LOCAL_ISN + 1));
+        }
+        //NewReno, half of the 10000 bytes in flight
+        assert_eq!(connection.congestion_control.ssthresh(), DATA_LENGTH / 2);
+        assert_eq!(connection.congestion_control.cwnd(), DATA_LENGTH / 2);
+        assert_eq!(flush(&mut connection, now), vec![LOCAL_ISN + 1]);
+    }
+
+    #[test]
+    fn acks_that_are_not_duplicates_are_not_counted() {
+        let (mut connection, now) = established_with_data_in_flight();
+        //a window update
+        receive(&mut connection, now, &segment_from_peer(FLAG_ACK, LOCAL_ISN + 1, PEER_WINDOW - 1));
+        //carries data
+        let mut outbound_buffer = [0u8; MTU];
+        let with_data = segment_from_peer(FLAG_ACK, LOCAL_ISN + 1, PEER_WINDOW - 1);
+        connection.process_incoming(now, &with_data, &[1u8; 10], &mut outbound_buffer).unwrap();
+        //the real thing, twice
+        let mut duplicate = segment_from_peer(FLAG_ACK, LOCAL_ISN + 1, PEER_WINDOW - 1);
+        duplicate.set_sequence_number(SeqNum::new(PEER_ISN + 11));
+        receive(&mut connection, now, &duplicate);
+        receive(&mut connection, now, &duplicate);
+        assert_eq!(connection.duplicate_acknowledgements, 2);
+        assert!(connection.recovery_point.is_none());
+    }
+
+    #[test]
+    fn new_data_acknowledged_resets_the_count() {
+        let (mut connection, now) = established_with_data_in_flight();
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1));
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1));
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1001));
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1001));
+        assert_eq!(connection.duplicate_acknowledgements, 1);
+        assert!(connection.recovery_point.is_none());
+    }
+
+    #[test]
+    fn partial_ack_retransmits_the_next_segment() {
+        let (mut connection, now) = established_with_data_in_flight();
+        for _ in 0..4 {
+            receive(&mut connection, now, &ack(LOCAL_ISN + 1));
+        }
+        assert_eq!(flush(&mut connection, now), vec![LOCAL_ISN + 1]);
+
+        receive(&mut connection, now, &ack(LOCAL_ISN + 2001));
+        assert!(connection.recovery_point.is_some());
+        assert_eq!(flush(&mut connection, now).first(), Some(&(LOCAL_ISN + 2001)));
+    }
+
+    #[test]
+    fn full_ack_ends_fast_recovery() {
+        let (mut connection, now) = established_with_data_in_flight();
+        for _ in 0..4 {
+            receive(&mut connection, now, &ack(LOCAL_ISN + 1));
+        }
+        flush(&mut connection, now);
+
+        receive(&mut connection, now, &ack(LOCAL_ISN + 1 + DATA_LENGTH as u32));
+        assert!(connection.recovery_point.is_none());
+        assert_eq!(connection.duplicate_acknowledgements, 0);
+        //nothing left in flight, RFC 6582 deflates to one segment more than that
+        assert_eq!(connection.congestion_control.cwnd(), 2 * PEER_MAXIMUM_SEGMENT_SIZE as usize);
+    }
+}
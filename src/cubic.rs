@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+use crate::congestion::{initial_window, AckEvent, CongestionControl};
+
+//RFC 9438 section 5, the cubic scaling constant and the multiplicative decrease factor
+const C: f64 = 0.4;
+const BETA: f64 = 0.7;
+//section 4.3, additive increase of the Reno-friendly estimate so it matches Reno on average
+const ALPHA: f64 = 3.0 * (1.0 - BETA) / (1.0 + BETA);
+
+//CUBIC as in RFC 9438. The window grows along a cubic curve whose plateau is the window the last
+//loss happened at, and never slower than Reno would over the same period. Window sizes in the
+//curve are worked out in segments, as the RFC does, but kept in bytes.
+pub struct Cubic {
+    maximum_segment_size: usize,
+    cwnd: f64,
+    ssthresh: usize,
+    //W_max, the window just before the last reduction, in segments
+    window_max: f64,
+    //K, time the curve takes to climb back to W_max
+    k: f64,
+    //start of the current congestion avoidance epoch
+    epoch_start: Option<Instant>,
+    //W_est, the Reno-friendly estimate, in segments
+    window_estimate: f64,
+    //latest RTT sample, the curve is evaluated one RTT ahead
+    rtt: Duration,
+}
+
+impl Cubic {
+
+    pub fn new(maximum_segment_size: usize) -> Self {
+        Cubic {
+            maximum_segment_size,
+            cwnd: initial_window(maximum_segment_size) as f64,
+            ssthresh: usize::MAX,
+            window_max: 0.0,
+            k: 0.0,
+            epoch_start: None,
+            window_estimate: 0.0,
+            rtt: Duration::ZERO,
+        }
+    }
+
+    fn segments(&self, bytes: f64) -> f64 {
+        bytes / self.maximum_segment_size as f64
+    }
+
+    //W_cubic(t), section 4.2
+    fn cubic_window(&self, t: f64) -> f64 {
+        C * (t - self.k).powi(3) + self.window_max
+    }
+
+    //section 4.6 and 4.7, shared by loss and timeout
+    fn reduce(&mut self) {
+        let cwnd_segments = self.segments(self.cwnd);
+        //fast convergence, give up some bandwidth to a newer flow if we didn't get back to W_max
+        self.window_max = if cwnd_segments < self.window_max {
+            cwnd_segments * (1.0 + BETA) / 2.0
+        } else {
+            cwnd_segments
+        };
+        self.ssthresh = ((self.cwnd * BETA) as usize).max(2 * self.maximum_segment_size);
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+
+    fn name(&self) -> &'static str {
+        "cubic"
+    }
+
+    fn set_maximum_segment_size(&mut self, maximum_segment_size: usize) {
+        self.maximum_segment_size = maximum_segment_size;
+        self.cwnd = initial_window(maximum_segment_size) as f64;
+    }
+
+    fn on_ack(&mut self, event: &AckEvent) {
+        if let Some(rtt) = event.rtt {
+            self.rtt = rtt;
+        }
+        if event.in_recovery {
+            return;
+        }
+        if (self.cwnd as usize) < self.ssthresh {
+            self.cwnd += event.acknowledged_bytes.min(self.maximum_segment_size) as f64;
+            return;
+        }
+
+        let cwnd_segments = self.segments(self.cwnd);
+        let epoch_start = match self.epoch_start {
+            Some(epoch_start) => epoch_start,
+            None => {
+                //section 4.2, a new epoch starts on the first ACK of congestion avoidance
+                self.epoch_start = Some(event.now);
+                self.window_estimate = cwnd_segments;
+                if cwnd_segments < self.window_max {
+                    self.k = ((self.window_max - cwnd_segments) / C).cbrt();
+                } else {
+                    self.k = 0.0;
+                    self.window_max = cwnd_segments;
+                }
+                event.now
+            }
+        };
+
+        let acknowledged_segments = self.segments(event.acknowledged_bytes as f64);
+        let t = event.now.saturating_duration_since(epoch_start).as_secs_f64();
+
+        //section 4.3, the estimate grows like Reno would, faster once past W_max
+        let alpha = if self.window_estimate >= self.window_max { 1.0 } else { ALPHA };
+        self.window_estimate += alpha * acknowledged_segments / cwnd_segments;
+
+        if self.cubic_window(t) < self.window_estimate {
+            //Reno-friendly region
+            self.cwnd = self.window_estimate * self.maximum_segment_size as f64;
+            return;
+        }
+
+        //sections 4.4 and 4.5, head for where the curve will be an RTT from now
+        let target = self.cubic_window(t + self.rtt.as_secs_f64()).clamp(cwnd_segments, 1.5 * cwnd_segments);
+        let increase = (target - cwnd_segments) / cwnd_segments * acknowledged_segments;
+        self.cwnd += increase * self.maximum_segment_size as f64;
+    }
+
+    fn on_loss(&mut self, _bytes_in_flight: usize, _now: Instant) {
+        self.reduce();
+        self.cwnd = self.ssthresh as f64;
+    }
+
+    fn on_rto(&mut self, _bytes_in_flight: usize, _now: Instant) {
+        self.reduce();
+        self.cwnd = self.maximum_segment_size as f64;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd as usize
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{Cubic, BETA, C};
+    use crate::congestion::{AckEvent, CongestionControl};
+
+    const MSS: usize = 1000;
+    const RTT: Duration = Duration::from_millis(100);
+
+    fn ack(now: Instant, acknowledged_bytes: usize, rtt: Duration) -> AckEvent {
+        AckEvent {
+            now,
+            acknowledged_bytes,
+            delivered_bytes: acknowledged_bytes,
+            delivered: 0,
+            bytes_in_flight: 0,
+            rtt: Some(rtt),
+            rate_sample: None,
+            in_recovery: false,
+        }
+    }
+
+    //a whole window acknowledged a segment at a time, all as now
+    fn ack_window(cubic: &mut Cubic, now: Instant, rtt: Duration) {
+        for _ in 0..cubic.cwnd() / MSS {
+            cubic.on_ack(&ack(now, MSS, rtt));
+        }
+    }
+
+    fn segments(cubic: &Cubic) -> f64 {
+        cubic.cwnd / MSS as f64
+    }
+
+    //a connection that just lost a segment with a 100 segment window
+    fn after_loss_at_100_segments(now: Instant) -> Cubic {
+        let mut cubic = Cubic::new(MSS);
+        cubic.cwnd = (100 * MSS) as f64;
+        cubic.on_loss(100 * MSS, now);
+        cubic
+    }
+
+    #[test]
+    fn slow_start_adds_at_most_a_segment_per_ack() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new(MSS);
+        assert_eq!(cubic.cwnd(), 10 * MSS);
+        cubic.on_ack(&ack(now, MSS, RTT));
+        assert_eq!(cubic.cwnd(), 11 * MSS);
+        //a stretch ACK still only counts once
+        cubic.on_ack(&ack(now, 3 * MSS, RTT));
+        assert_eq!(cubic.cwnd(), 12 * MSS);
+        //nothing grows during recovery
+        let mut in_recovery = ack(now, MSS, RTT);
+        in_recovery.in_recovery = true;
+        cubic.on_ack(&in_recovery);
+        assert_eq!(cubic.cwnd(), 12 * MSS);
+    }
+
+    //RFC 9438 sections 4.6 and 4.7
+    #[test]
+    fn loss_cuts_the_window_by_beta_with_fast_convergence() {
+        let now = Instant::now();
+        let mut cubic = after_loss_at_100_segments(now);
+        assert_eq!(cubic.ssthresh(), (100.0 * BETA) as usize * MSS);
+        assert_eq!(cubic.cwnd(), cubic.ssthresh());
+        assert_eq!(cubic.window_max, 100.0);
+
+        //lost again before getting back to W_max, so W_max is lowered further to make room
+        cubic.on_loss(70 * MSS, now);
+        assert!((cubic.window_max - 70.0 * (1.0 + BETA) / 2.0).abs() < 1e-9);
+        assert_eq!(cubic.ssthresh(), (70.0 * BETA) as usize * MSS);
+
+        cubic.on_rto(0, now);
+        assert_eq!(cubic.cwnd(), MSS);
+        assert_eq!(cubic.ssthresh(), (49.0 * MSS as f64 * BETA) as usize);
+    }
+
+    //W_cubic(t) = C * (t - K)^3 + W_max, with K = cbrt(W_max * (1 - beta) / C) after a reduction
+    #[test]
+    fn the_window_follows_the_cubic_curve() {
+        let start = Instant::now();
+        let mut cubic = after_loss_at_100_segments(start);
+        ack_window(&mut cubic, start, RTT);
+        let k = (100.0 * (1.0 - BETA) / C).cbrt();
+        assert!((cubic.k - k).abs() < 1e-9);
+
+        let mut now = start;
+        for round in 1..=80 {
+            now += RTT;
+            ack_window(&mut cubic, now, RTT);
+            //each round heads for where the curve is an RTT later, unless Reno would be further on
+            let t = (round + 1) as f64 * RTT.as_secs_f64();
+            let expected = (C * (t - k).powi(3) + 100.0).max(cubic.window_estimate);
+            assert!((segments(&cubic) - expected).abs() / expected < 0.03, "round {round}: {} segments, curve says {expected}", segments(&cubic));
+        }
+        //flat around W_max, then probing well past it
+        assert!(segments(&cubic) > 120.0);
+    }
+
+    //with a short RTT Reno would grow faster than the curve does, CUBIC is never slower than that
+    #[test]
+    fn short_rtts_are_in_the_reno_friendly_region() {
+        let rtt = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut cubic = after_loss_at_100_segments(start);
+        let mut now = start;
+        for _ in 0..100 {
+            ack_window(&mut cubic, now, rtt);
+            now += rtt;
+        }
+        let t = now.saturating_duration_since(start).as_secs_f64();
+        assert!(cubic.window_estimate > cubic.cubic_window(t));
+        assert_eq!(cubic.cwnd, cubic.window_estimate * MSS as f64);
+        //alpha_cubic per RTT below W_max, one segment per RTT above it
+        assert!(segments(&cubic) > 100.0 + 1.0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::time::{Duration, Instant};
+
+//the connection's delivery counters as they stood when a segment was sent, kept with the segment
+//so the rate can be measured over the interval between its send and its ACK
+#[derive(Debug, Clone, Copy)]
+pub struct DeliverySnapshot {
+    pub delivered: u64,
+    pub delivered_time: Instant,
+    pub first_sent_time: Instant,
+    pub is_app_limited: bool,
+}
+
+//one delivery rate measurement, taken per ACK
+#[derive(Debug, Clone, Copy)]
+pub struct RateSample {
+    //bytes per second
+    pub delivery_rate: u64,
+    //total delivered when the most recently sent of the segments this ACK covered went out
+    pub prior_delivered: u64,
+    //bytes delivered over the sample's interval
+    pub delivered: u64,
+    pub interval: Duration,
+    //the application didn't have enough data to fill the window, so the rate undershoots the path
+    pub is_app_limited: bool,
+}
+
+//delivery rate estimation as described in draft-cheng-iccrg-delivery-rate-estimation. Every
+//segment remembers how much had been delivered when it was sent, an ACK for it then tells us how
+//much got delivered since and over how long.
+pub struct DeliveryRateEstimator {
+    //bytes cumulatively acknowledged or SACKed over the connection's lifetime
+    delivered: u64,
+    delivered_time: Instant,
+    //send time of the most recently sent segment that has been delivered
+    first_sent_time: Instant,
+    //delivered count at which the current application limited stretch ends, 0 when not limited
+    app_limited_until: u64,
+    min_rtt: Option<Duration>,
+    //the sample being assembled from the segments one ACK covers
+    sample: Option<PendingSample>,
+}
+
+struct PendingSample {
+    prior_delivered: u64,
+    prior_time: Instant,
+    send_elapsed: Duration,
+    is_app_limited: bool,
+}
+
+impl DeliveryRateEstimator {
+
+    pub fn new(now: Instant) -> Self {
+        DeliveryRateEstimator {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            app_limited_until: 0,
+            min_rtt: None,
+            sample: None,
+        }
+    }
+
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    //called for every segment as it is (re)sent. A send that starts from an empty network opens a
+    //new measurement interval, otherwise idle time would count against the rate.
+    pub fn on_send(&mut self, bytes_in_flight: usize, now: Instant) -> DeliverySnapshot {
+        if bytes_in_flight == 0 {
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        DeliverySnapshot {
+            delivered: self.delivered,
+            delivered_time: self.delivered_time,
+            first_sent_time: self.first_sent_time,
+            is_app_limited: self.app_limited_until != 0,
+        }
+    }
+
+    //the sender ran out of data with room left in the window, samples until everything now in
+    //flight has been delivered only show what the application offered
+    pub fn on_application_limited(&mut self, bytes_in_flight: usize) {
+        self.app_limited_until = (self.delivered + bytes_in_flight as u64).max(1);
+    }
+
+    //length bytes of a segment sent at sent_time were acknowledged or SACKed
+    pub fn on_delivered(&mut self, snapshot: &DeliverySnapshot, length: u32, sent_time: Instant, now: Instant) {
+        self.delivered += length as u64;
+        self.delivered_time = now;
+        //the most recently sent segment the ACK covers decides the interval
+        let newer = self.sample.as_ref().is_none_or(|sample| snapshot.delivered >= sample.prior_delivered);
+        if newer {
+            self.sample = Some(PendingSample {
+                prior_delivered: snapshot.delivered,
+                prior_time: snapshot.delivered_time,
+                send_elapsed: sent_time.saturating_duration_since(snapshot.first_sent_time),
+                is_app_limited: snapshot.is_app_limited,
+            });
+            self.first_sent_time = sent_time;
+        }
+    }
+
+    //finishes the sample for the ACK just processed, None if it didn't deliver anything or the
+    //interval is too short to be trusted
+    pub fn take_sample(&mut self, rtt: Option<Duration>) -> Option<RateSample> {
+        if let Some(rtt) = rtt {
+            self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+        }
+        if self.app_limited_until != 0 && self.delivered > self.app_limited_until {
+            self.app_limited_until = 0;
+        }
+        let sample = self.sample.take()?;
+        let ack_elapsed = self.delivered_time.saturating_duration_since(sample.prior_time);
+        //the slower of the send and ACK rates, ACK compression can't make the path look faster
+        let interval = sample.send_elapsed.max(ack_elapsed);
+        let delivered = self.delivered - sample.prior_delivered;
+        if interval.is_zero() || self.min_rtt.is_some_and(|min_rtt| interval < min_rtt) {
+            return None;
+        }
+        Some(RateSample {
+            delivery_rate: (delivered as f64 / interval.as_secs_f64()) as u64,
+            prior_delivered: sample.prior_delivered,
+            delivered,
+            interval,
+            is_app_limited: sample.is_app_limited,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::DeliveryRateEstimator;
+
+    const SEGMENT: u32 = 1000;
+
+    fn millis(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    //the rate is what got delivered over the longer of the send and ACK intervals
+    #[test]
+    fn rate_is_delivered_over_the_longer_interval() {
+        let start = Instant::now();
+        let mut estimator = DeliveryRateEstimator::new(start);
+        let first = estimator.on_send(0, start);
+        let second = estimator.on_send(SEGMENT as usize, millis(start, 50));
+
+        estimator.on_delivered(&first, SEGMENT, start, millis(start, 100));
+        let sample = estimator.take_sample(Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(sample.interval, Duration::from_millis(100));
+        assert_eq!(sample.delivery_rate, 10_000);
+        assert_eq!(sample.prior_delivered, 0);
+
+        //sent before the first was delivered, so measured from the same point
+        estimator.on_delivered(&second, SEGMENT, millis(start, 50), millis(start, 150));
+        let sample = estimator.take_sample(Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(sample.delivered, 2 * SEGMENT as u64);
+        assert_eq!(sample.interval, Duration::from_millis(150));
+        assert_eq!(sample.delivery_rate, 13_333);
+        assert_eq!(estimator.delivered(), 2 * SEGMENT as u64);
+    }
+
+    //ACKs bunched together can't make the path look faster than the data went out
+    #[test]
+    fn compressed_acks_are_limited_by_the_send_interval() {
+        let start = Instant::now();
+        let mut estimator = DeliveryRateEstimator::new(start);
+        let first = estimator.on_send(0, start);
+        let second = estimator.on_send(SEGMENT as usize, millis(start, 100));
+        estimator.on_delivered(&first, SEGMENT, start, millis(start, 100));
+        estimator.take_sample(Some(Duration::from_millis(100))).unwrap();
+        let third = estimator.on_send(SEGMENT as usize, millis(start, 150));
+        estimator.on_delivered(&second, SEGMENT, millis(start, 100), millis(start, 152));
+        estimator.take_sample(None).unwrap();
+
+        //acknowledged only 55ms after the ACK it is measured from, but it went out 150ms after
+        //the segment that ACK was for
+        estimator.on_delivered(&third, SEGMENT, millis(start, 150), millis(start, 155));
+        let sample = estimator.take_sample(None).unwrap();
+        assert_eq!(sample.interval, Duration::from_millis(150));
+        assert_eq!(sample.delivered, 2 * SEGMENT as u64);
+        assert_eq!(sample.delivery_rate, 13_333);
+    }
+
+    #[test]
+    fn samples_shorter_than_the_min_rtt_are_dropped() {
+        let start = Instant::now();
+        let mut estimator = DeliveryRateEstimator::new(start);
+        let snapshot = estimator.on_send(0, start);
+        estimator.on_delivered(&snapshot, SEGMENT, start, millis(start, 100));
+        assert!(estimator.take_sample(Some(Duration::from_millis(100))).is_some());
+
+        let snapshot = estimator.on_send(0, millis(start, 200));
+        estimator.on_delivered(&snapshot, SEGMENT, millis(start, 200), millis(start, 250));
+        assert!(estimator.take_sample(Some(Duration::from_millis(50))).is_some());
+        //nothing delivered, nothing to say
+        assert!(estimator.take_sample(None).is_none());
+
+        let snapshot = estimator.on_send(0, millis(start, 300));
+        estimator.on_delivered(&snapshot, SEGMENT, millis(start, 300), millis(start, 340));
+        assert!(estimator.take_sample(None).is_none());
+    }
+
+    //samples stay marked until everything in flight when the application ran dry is delivered
+    #[test]
+    fn application_limited_stretches_are_marked() {
+        let start = Instant::now();
+        let mut estimator = DeliveryRateEstimator::new(start);
+        let first = estimator.on_send(0, start);
+        estimator.on_application_limited(SEGMENT as usize);
+        let second = estimator.on_send(SEGMENT as usize, millis(start, 10));
+
+        estimator.on_delivered(&first, SEGMENT, start, millis(start, 100));
+        assert!(!estimator.take_sample(None).unwrap().is_app_limited);
+        estimator.on_delivered(&second, SEGMENT, millis(start, 10), millis(start, 110));
+        assert!(estimator.take_sample(None).unwrap().is_app_limited);
+
+        let third = estimator.on_send(0, millis(start, 200));
+        estimator.on_delivered(&third, SEGMENT, millis(start, 200), millis(start, 300));
+        assert!(!estimator.take_sample(None).unwrap().is_app_limited);
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use crate::congestion::CongestionAlgorithm;
+use crate::connections::SocketPair;
+
+//per socket settings a client can change, they apply to the socket's connection
+#[derive(Debug, Clone, Copy)]
+pub enum SocketOption {
+    CongestionControl(CongestionAlgorithm),
+    //acknowledge every segment straight away instead of delaying ACKs
+    QuickAck(bool),
+    //send small segments without waiting for outstanding data to be acknowledged (Nagle off)
+    NoDelay(bool),
+    //only send full segments until uncorked
+    Cork(bool),
+    //probe the peer once the connection has been idle for a while
+    KeepAlive(bool),
+    //idle time before the first probe, time between probes, and how many may go unanswered
+    KeepIdle(Duration),
+    KeepInterval(Duration),
+    KeepCount(u32),
+}
+
+//requests from the unix socket control plane that need the connection table
+pub enum SocketCommand {
+    //options are whatever the client set on the socket before connecting
+    Connect { socket_id: u32, socket_pair: SocketPair, options: Vec<SocketOption> },
+    //options set on a listening socket carry over to every connection it accepts
+    Listen { socket_id: u32, port: u16, backlog: usize, options: Vec<SocketOption> },
+    //non-blocking accepts are answered straight away when nothing is waiting
+    Accept { socket_id: u32, nonblocking: bool },
+    Send { socket_id: u32, data: Vec<u8> },
+    Receive { socket_id: u32, maximum_length: usize },
+    SetOption { socket_id: u32, option: SocketOption },
+    Close { socket_id: u32 },
+}
+
+//the unix socket threads hand commands to the stack through this. Every send also pokes a socket
+//pair so the stack's poll wakes up, the channel alone can't be waited on next to the tun fd.
+#[derive(Clone)]
+pub struct CommandSender {
+    sender: Sender<SocketCommand>,
+    waker: Arc<UnixStream>,
+}
+
+pub struct CommandReceiver {
+    receiver: Receiver<SocketCommand>,
+    wakeup: UnixStream,
+}
+
+pub fn command_channel() -> io::Result<(CommandSender, CommandReceiver)> {
+    let (sender, receiver) = mpsc::channel();
+    let (waker, wakeup) = UnixStream::pair()?;
+    wakeup.set_nonblocking(true)?;
+    waker.set_nonblocking(true)?;
+    Ok((
+        CommandSender { sender, waker: Arc::new(waker) },
+        CommandReceiver { receiver, wakeup },
+    ))
+}
+
+impl CommandSender {
+    pub fn send(&self, command: SocketCommand) -> Result<(), &'static str> {
+        self.sender
+            .send(command)
+            .map_err(|_| "[ERROR]: main loop is no longer accepting commands")?;
+        //a full socket buffer already means a wakeup is pending, so WouldBlock is fine
+        match (&*self.waker).write(&[1]) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(_) => Err("[ERROR]: failed to wake the main loop"),
+        }
+    }
+}
+
+impl CommandReceiver {
+    //everything queued so far, clearing the wakeup bytes along the way
+    pub fn drain(&mut self) -> Vec<SocketCommand> {
+        let mut discard = [0u8; 64];
+        while matches!(self.wakeup.read(&mut discard), Ok(n) if n > 0) {}
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl AsRawFd for CommandReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wakeup.as_raw_fd()
+    }
+}
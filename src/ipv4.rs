@@ -63,6 +63,9 @@ impl Ipv4 {
         let destination_address = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
 
         let header_length = ( version_and_ihl & 0x0F ) as usize * 4;
+        if header_length < 20 || header_length > data.len() {
+            return Err("[ERROR]: Ipv4 header length field is out of range");
+        }
 
         let calculated_checksum = calculate_checksum(&data[..header_length]);
         if calculated_checksum != header_checksum {
@@ -87,9 +90,7 @@ impl Ipv4 {
 
     pub fn serialize(&self) -> Vec<u8> {
 
-        let mut bytes = Vec::new();
-        bytes.push(self.version_and_ihl);
-        bytes.push(self.type_of_service);
+        let mut bytes = vec![self.version_and_ihl, self.type_of_service];
 
         bytes.push((self.total_length >> 8) as u8);
         bytes.push(self.total_length as u8);
@@ -114,10 +115,10 @@ impl Ipv4 {
 
     }
 
-    pub fn calculate_and_set_checksum ( serialized_packet: &mut Vec<u8> ) {
-        let checksum = calculate_checksum(serialized_packet.as_slice());
-        serialized_packet[11] = ( checksum  >> 8 )as u8;
-        serialized_packet[12] = checksum as u8;
+    pub fn calculate_and_set_checksum ( serialized_packet: &mut [u8] ) {
+        let checksum = calculate_checksum(serialized_packet);
+        serialized_packet[10] = ( checksum  >> 8 )as u8;
+        serialized_packet[11] = checksum as u8;
     }
 
     pub fn protocol(&self) -> u8 {
@@ -0,0 +1,215 @@
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::time::{Duration, Instant};
+use crate::connections::SocketPair;
+use crate::seqnum::SeqNum;
+
+//a SYN cookie's counter moves on this often, the cookie is good for as long as its counter is
+//the current or the previous one
+pub const SYN_COOKIE_PERIOD: Duration = Duration::from_secs(64);
+
+//RFC 6528 initial sequence numbers: ISN = M + F(localip, localport, remoteip, remoteport, secretkey)
+//where M ticks every 4 microseconds and F is a keyed hash. The clock keeps ISNs for a reused 4-tuple
+//moving forward, the hash keeps them unguessable to anyone who doesn't know the secret.
+pub struct IsnGenerator {
+    secret: [u64; 2],
+    clock_start: Instant,
+}
+
+impl IsnGenerator {
+
+    //random per-boot secret and a running clock
+    pub fn new() -> Self {
+        IsnGenerator {
+            secret: Self::random_secret(),
+            clock_start: Instant::now(),
+        }
+    }
+
+    //deterministic mode, the same seed always yields the same ISN for the same socket pair at the
+    //same point on the clock. The clock still runs so a reused socket pair moves forward as usual.
+    pub fn seeded(seed: u64) -> Self {
+        let mut state = seed;
+        IsnGenerator {
+            secret: [splitmix64(&mut state), splitmix64(&mut state)],
+            clock_start: Instant::now(),
+        }
+    }
+
+    pub fn generate(&self, socket_pair: &SocketPair, now: Instant) -> SeqNum {
+        let hash = self.keyed_hash(socket_pair, &[]);
+        let clock = (now.saturating_duration_since(self.clock_start).as_micros() / 4) as u32;
+        SeqNum::new(clock.wrapping_add(hash))
+    }
+
+    //per-connection offset for RFC 7323 timestamps, the same keyed hash with a different input so
+    //it has nothing in common with the ISN
+    pub fn timestamp_offset(&self, socket_pair: &SocketPair) -> u32 {
+        self.keyed_hash(socket_pair, b"ts")
+    }
+
+    //the time part of a SYN cookie
+    pub fn syn_cookie_counter(&self, now: Instant) -> u32 {
+        (now.saturating_duration_since(self.clock_start).as_secs() / SYN_COOKIE_PERIOD.as_secs()) as u32
+    }
+
+    //the part of a SYN cookie only someone who knows the secret can produce, over the peer's ISN
+    //and the counter as well as the socket pair
+    pub fn syn_cookie_hash(&self, socket_pair: &SocketPair, peer_initial_sequence_number: SeqNum, counter: u32) -> u32 {
+        let mut domain = b"cookie".to_vec();
+        domain.extend_from_slice(&peer_initial_sequence_number.value().to_be_bytes());
+        domain.extend_from_slice(&counter.to_be_bytes());
+        self.keyed_hash(socket_pair, &domain)
+    }
+
+    fn keyed_hash(&self, socket_pair: &SocketPair, domain: &[u8]) -> u32 {
+        let mut message = Vec::with_capacity(12 + domain.len());
+        message.extend_from_slice(&socket_pair.dest_ip.octets());
+        message.extend_from_slice(&socket_pair.dest_port.to_be_bytes());
+        message.extend_from_slice(&socket_pair.src_ip.octets());
+        message.extend_from_slice(&socket_pair.src_port.to_be_bytes());
+        message.extend_from_slice(domain);
+        siphash24(self.secret, &message) as u32
+    }
+
+    fn random_secret() -> [u64; 2] {
+        let mut bytes = [0u8; 16];
+        match File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut bytes)) {
+            Ok(()) => [
+                u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+                u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+            ],
+            Err(e) => {
+                //RandomState is seeded from the OS as well, good enough if urandom is missing
+                eprintln!("[ERROR]: could not read /dev/urandom for the ISN secret, falling back: {e}");
+                [RandomState::new().build_hasher().finish(), RandomState::new().build_hasher().finish()]
+            }
+        }
+    }
+}
+
+impl Default for IsnGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+//SipHash-2-4 as described in the reference paper by Aumasson and Bernstein
+fn siphash24(key: [u64; 2], message: &[u8]) -> u64 {
+    let mut v = [
+        key[0] ^ 0x736f_6d65_7073_6575,
+        key[1] ^ 0x646f_7261_6e64_6f6d,
+        key[0] ^ 0x6c79_6765_6e65_7261,
+        key[1] ^ 0x7465_6462_7974_6573,
+    ];
+
+    let mut chunks = message.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(&mut v, u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+
+    //last block carries the remaining bytes and the message length in its top byte
+    let remainder = chunks.remainder();
+    let mut last = [0u8; 8];
+    last[..remainder.len()].copy_from_slice(remainder);
+    compress(&mut v, u64::from_le_bytes(last) | ((message.len() as u64 & 0xff) << 56));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+//two compression rounds per message block
+fn compress(v: &mut [u64; 4], m: u64) {
+    v[3] ^= m;
+    sip_round(v);
+    sip_round(v);
+    v[0] ^= m;
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]); v[1] = v[1].rotate_left(13); v[1] ^= v[0]; v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]); v[3] = v[3].rotate_left(16); v[3] ^= v[2];
+    v[0] = v[0].wrapping_add(v[3]); v[3] = v[3].rotate_left(21); v[3] ^= v[0];
+    v[2] = v[2].wrapping_add(v[1]); v[1] = v[1].rotate_left(17); v[1] ^= v[2]; v[2] = v[2].rotate_left(32);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use super::{siphash24, IsnGenerator};
+    use crate::connections::{SocketPair, LOCAL_ADDRESS};
+
+    fn socket_pair(src_port: u16) -> SocketPair {
+        SocketPair {
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dest_ip: LOCAL_ADDRESS,
+            src_port,
+            dest_port: 49152,
+        }
+    }
+
+    //the reference vectors from the SipHash paper, key 00..0f over the messages 00, 00 01, ...
+    #[test]
+    fn siphash24_matches_the_reference_vectors() {
+        let key = [0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908];
+        let message: Vec<u8> = (0..64).collect();
+        for (length, expected) in [
+            (0, 0x726f_db47_dd0e_0e31),
+            (1, 0x74f8_39c5_93dc_67fd),
+            (2, 0x0d6c_8009_d9a9_4f5a),
+            (3, 0x8567_6696_d7fb_7e2d),
+            (7, 0xab02_00f5_8b01_d137),
+            (8, 0x93f5_f579_9a93_2462),
+            (15, 0xa129_ca61_49be_45e5),
+            (16, 0x3f2a_cc7f_57c2_9bdb),
+            (63, 0x958a_324c_eb06_4572),
+        ] {
+            assert_eq!(siphash24(key, &message[..length]), expected, "message of {length} bytes");
+        }
+    }
+
+    #[test]
+    fn seeded_isns_are_deterministic() {
+        let first = IsnGenerator::seeded(7);
+        let mut second = IsnGenerator::seeded(7);
+        second.clock_start = first.clock_start;
+        let now = first.clock_start + Duration::from_millis(250);
+        assert_eq!(first.generate(&socket_pair(9000), now), second.generate(&socket_pair(9000), now));
+        assert_eq!(first.timestamp_offset(&socket_pair(9000)), second.timestamp_offset(&socket_pair(9000)));
+    }
+
+    #[test]
+    fn seeded_isns_depend_on_the_socket_pair_and_the_seed() {
+        let generator = IsnGenerator::seeded(7);
+        let mut other_seed = IsnGenerator::seeded(8);
+        other_seed.clock_start = generator.clock_start;
+        let now = generator.clock_start;
+        let isn = generator.generate(&socket_pair(9000), now);
+        assert_ne!(isn, generator.generate(&socket_pair(9001), now));
+        assert_ne!(isn, other_seed.generate(&socket_pair(9000), now));
+    }
+
+    //M in RFC 6528 ticks every 4 microseconds
+    #[test]
+    fn seeded_isns_advance_with_the_clock() {
+        let generator = IsnGenerator::seeded(7);
+        let start = generator.clock_start;
+        let isn = generator.generate(&socket_pair(9000), start);
+        assert_eq!(generator.generate(&socket_pair(9000), start + Duration::from_micros(4000)), isn + 1000);
+        assert_eq!(generator.generate(&socket_pair(9000), start + Duration::from_secs(1)), isn + 250_000);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+use crate::connections::SocketPair;
+use crate::events::SocketOption;
+
+//listen backlogs are capped at this, same as the usual somaxconn
+const MAXIMUM_BACKLOG: usize = 4096;
+//for Listen messages that don't carry one
+pub const DEFAULT_BACKLOG: usize = 128;
+
+//a listening socket. Its connections sit in the stack's connection table like any other, the
+//queues only say which of them are still in the handshake and which are done with it and waiting
+//for an accept.
+pub struct Listener {
+    socket_id: u32,
+    backlog: usize,
+    //whatever the client set on the listening socket, every connection it accepts starts with them
+    options: Vec<SocketOption>,
+    syn_queue: VecDeque<SocketPair>,
+    accept_queue: VecDeque<SocketPair>,
+}
+
+impl Listener {
+
+    pub fn new(socket_id: u32, backlog: usize, options: Vec<SocketOption>) -> Self {
+        Listener {
+            socket_id,
+            backlog: Self::clamp_backlog(backlog),
+            options,
+            syn_queue: VecDeque::new(),
+            accept_queue: VecDeque::new(),
+        }
+    }
+
+    //a zero backlog still lets one connection through
+    fn clamp_backlog(backlog: usize) -> usize {
+        backlog.clamp(1, MAXIMUM_BACKLOG)
+    }
+
+    pub fn socket_id(&self) -> u32 {
+        self.socket_id
+    }
+
+    pub fn options(&self) -> &[SocketOption] {
+        &self.options
+    }
+
+    //listening again on the same socket only changes the backlog, anything queued stays queued
+    pub fn set_backlog(&mut self, backlog: usize) {
+        self.backlog = Self::clamp_backlog(backlog);
+    }
+
+    pub fn syn_queue_length(&self) -> usize {
+        self.syn_queue.len()
+    }
+
+    pub fn syn_queue_is_full(&self) -> bool {
+        self.syn_queue.len() >= self.backlog
+    }
+
+    pub fn accept_queue_is_full(&self) -> bool {
+        self.accept_queue.len() >= self.backlog
+    }
+
+    pub fn add_half_open(&mut self, socket_pair: SocketPair) {
+        self.syn_queue.push_back(socket_pair);
+    }
+
+    //the handshake is done, from the SYN queue (if it was ever there, SYN cookies skip it) over to
+    //the accept queue
+    pub fn complete(&mut self, socket_pair: SocketPair) {
+        self.syn_queue.retain(|queued| *queued != socket_pair);
+        self.accept_queue.push_back(socket_pair);
+    }
+
+    pub fn next_accepted(&self) -> Option<SocketPair> {
+        self.accept_queue.front().copied()
+    }
+
+    //the connection was accepted or went away, either way it's no longer queued here
+    pub fn forget(&mut self, socket_pair: &SocketPair) {
+        self.syn_queue.retain(|queued| queued != socket_pair);
+        self.accept_queue.retain(|queued| queued != socket_pair);
+    }
+
+    //everything still queued, for when the listening socket is closed
+    pub fn take_queued(&mut self) -> Vec<SocketPair> {
+        self.syn_queue.drain(..).chain(self.accept_queue.drain(..)).collect()
+    }
+}
@@ -1,118 +1,92 @@
-use ipv4::Ipv4;
-use tcp::Tcp;
-pub mod utility;
+use std::time::Duration;
 use tun_tap::{Iface,Mode};
-use std::collections::HashMap;
+pub mod utility;
 pub mod ipv4;
+pub mod rack;
+pub mod isn;
+pub mod mitigations;
+pub mod bbr;
+pub mod congestion;
+pub mod cubic;
+pub mod delivery;
+pub mod newreno;
 pub mod tcp;
 pub mod connections;
+pub mod events;
+pub mod listener;
+pub mod reassembly;
+pub mod retransmission;
+pub mod seqnum;
+pub mod stack;
+pub mod syncookies;
+pub mod timer;
+pub mod timestamps;
 pub mod unixsocket;
-use connections::{Connection,SocketPair};
+use isn::IsnGenerator;
+use congestion::CongestionAlgorithm;
+use stack::{Stack, StackConfig};
 use unixsocket::UnixSocketManager;
 
 fn main()  {
 
     let iface = Iface::new("mytun", Mode::Tun).expect("Failed to create a TUN device");
-    // 
-    let mut buffer = [0u8; 1504]; // MTU + 4 for the header
-    let mut nbytes: usize;
 
-    let mut connection_table = HashMap::<SocketPair,Connection>::new();
-    UnixSocketManager::initialize().expect("[ERROR]: Failed to initialize UnixSocketManager");
+    let (command_sender, command_receiver) = events::command_channel().expect("[ERROR]: Failed to create the command channel");
+    UnixSocketManager::initialize(command_sender).expect("[ERROR]: Failed to initialize UnixSocketManager");
 
-    loop { 
-        nbytes = iface.recv(&mut buffer).unwrap();
-        let _flags = u16::from_be_bytes([buffer[0], buffer[2]]);
-        let ethertype = u16::from_be_bytes([buffer[2], buffer[3]]);
-
-        if ethertype != 0x0800 {
-            //if not ipv4
-            continue;
-        }
+    //setting RUST_SPACE_TCP_ISN_SEED fixes the secret behind initial sequence numbers, for tests only
+    let isn_generator = match std::env::var("RUST_SPACE_TCP_ISN_SEED").ok().and_then(|seed| seed.parse::<u64>().ok()) {
+        Some(seed) => {
+            println!("[INFO]: using seeded initial sequence numbers");
+            IsnGenerator::seeded(seed)
+        },
+        None => IsnGenerator::new(),
+    };
 
-        match Ipv4::deserialize(&buffer[4..nbytes]) {
-            Ok(ipv4header) => {
-                println!("Ipv4 header: {:?}", ipv4header);
-                if ipv4header.protocol() == 0x06 {
-                    //bail here instead of when attempting to read data into a TcpHeaderSLice later
-                    //down the line
-                    continue;
-                }
-                let src = ipv4header.source_ip();
-                let dst = ipv4header.destination_ip();
+    let mut config = StackConfig::default();
 
-                match Tcp::deserialize(&buffer[4+ipv4header.serialize().len()..]) {
-                    Ok(tcpheader) => {
-                        if !UnixSocketManager::port_is_open(tcpheader.destination_port()) {
-                            println!("[INFO]: port blocked {}", tcpheader.destination_port());                            continue;
-                        }
-                        let mut outbound_packet_buffer = [0u8; 1500];
-                        let response_size: usize;
-                        let payload_starts_at = (4 + ipv4header.header_length_in_bytes()  + tcpheader.header_length_in_bytes()) as usize;
-                        let socket_pair = SocketPair {
-                           src_ip : src,
-                           dest_ip : dst, 
-                           dest_port : tcpheader.destination_port(),
-                           src_port : tcpheader.source_port(),
-                        };
-                        match connection_table.get_mut(&socket_pair) {
-                            Some(existing_connection)  => {
-                                 match existing_connection.process_incoming(&ipv4header, &tcpheader, &buffer[payload_starts_at..], &mut outbound_packet_buffer) {
-                                    Ok(length) => { response_size = length; },
-                                    Err(e) => { 
-                                        eprintln!("[ERROR]: {}", e);
-                                        continue
-                                    } 
-                                }
-                            },
-                            None => {
-                                if !tcpheader.is_syn_set() {
-                                    continue;
-                                }
-                                println!("New Connection: {}:{} -> {}:{} [SYN:{} ACK:{} FIN:{} RST:{}]",
-                                    src, tcpheader.source_port(),
-                                    dst, tcpheader.destination_port(),
-                                    tcpheader.is_syn_set(), tcpheader.is_ack_set(), tcpheader.is_fin_set(), tcpheader.is_rst_set());
-                                match connection_table.insert(socket_pair, Connection::default()) {
-                                    Some(mut existing_connection) => {
-                                         match existing_connection.process_incoming(&ipv4header, &tcpheader, &buffer[payload_starts_at..], &mut outbound_packet_buffer) {
-                                            Ok(length) => { response_size = length; },
-                                            Err(e) => { 
-                                                eprintln!("[ERROR]: {}", e);
-                                                continue
-                                            } 
-                                        }
-                                    }
-                                    None => {
-                                        eprintln!("[ERROR]: Tried to insert a new connection and didnt get a reference back");
-                                        continue;
-                                    }
-                                }
-                            }
-                        }
+    //RUST_SPACE_TCP_RECEIVE_BUFFER overrides the receive buffer, and so the window, new connections get
+    if let Some(size) = std::env::var("RUST_SPACE_TCP_RECEIVE_BUFFER").ok().and_then(|size| size.parse::<usize>().ok()) {
+        println!("[INFO]: using a receive buffer of {} bytes", size);
+        config.receive_buffer_size = size;
+    }
 
-                        if response_size == 0 {
-                            println!("[INFO]: no response generated during processing of inbound packet."); 
-                            continue;
-                        }
-                        //write to tun interface
-                        match iface.send(&outbound_packet_buffer[..response_size]) {
-                                Ok(bytecount) => {
-                                    println!("[INFO]: Successfully wrote {} bytes to the tunnel interface", bytecount);
-                                }
-                                Err(e) => {
-                                    eprintln!("[ERROR]: writing to tunnel interface: {}", e);
-                                }
-                        }
-                    }
-                    Err(_value) => continue,
-                }
-            
+    //RUST_SPACE_TCP_CONGESTION_CONTROL picks the congestion control connections use unless their socket says otherwise
+    if let Ok(name) = std::env::var("RUST_SPACE_TCP_CONGESTION_CONTROL") {
+        match CongestionAlgorithm::from_name(&name) {
+            Ok(algorithm) => {
+                println!("[INFO]: using {:?} congestion control", algorithm);
+                config.congestion_algorithm = algorithm;
             },
-            Err(value) => {
-                println!("Err {:?}", value)
-            }
+            Err(e) => eprintln!("{}", e),
         }
+    }
+
+    //RUST_SPACE_TCP_DELAYED_ACK_MS sets how long an ACK for in order data may be held back
+    if let Some(milliseconds) = std::env::var("RUST_SPACE_TCP_DELAYED_ACK_MS").ok().and_then(|milliseconds| milliseconds.parse::<u64>().ok()) {
+        println!("[INFO]: delaying ACKs by up to {} ms", milliseconds);
+        config.delayed_acknowledgement_timeout = Duration::from_millis(milliseconds);
+    }
+
+    //RUST_SPACE_TCP_TIME_WAIT_MS sets how long closed connections linger in TIME_WAIT
+    if let Some(milliseconds) = std::env::var("RUST_SPACE_TCP_TIME_WAIT_MS").ok().and_then(|milliseconds| milliseconds.parse::<u64>().ok()) {
+        println!("[INFO]: keeping connections in TIME_WAIT for {} ms", milliseconds);
+        config.time_wait_duration = Duration::from_millis(milliseconds);
+    }
+
+    //RUST_SPACE_TCP_TIME_WAIT_REUSE=0 stops new SYNs from taking over connections in TIME_WAIT
+    if let Ok(reuse) = std::env::var("RUST_SPACE_TCP_TIME_WAIT_REUSE") {
+        config.time_wait_reuse = reuse != "0";
+    }
+
+    //RUST_SPACE_TCP_SYN_COOKIE_THRESHOLD sets how many half-open connections there may be before SYN cookies take over
+    if let Some(threshold) = std::env::var("RUST_SPACE_TCP_SYN_COOKIE_THRESHOLD").ok().and_then(|threshold| threshold.parse::<usize>().ok()) {
+        println!("[INFO]: answering SYNs with cookies beyond {} half-open connections", threshold);
+        config.syn_cookie_threshold = threshold;
+    }
 
+    let mut stack = Stack::new(iface, command_receiver, isn_generator, config).expect("[ERROR]: Failed to put the TUN device into non-blocking mode");
+    if let Err(e) = stack.run() {
+        eprintln!("[ERROR]: event loop stopped: {}", e);
     }
 }
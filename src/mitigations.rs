@@ -0,0 +1,151 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+
+//RFC 5961 section 7, challenge ACKs across all connections are capped at this many a second so
+//they can't be used to make us flood the network
+const CHALLENGE_ACK_LIMIT: u32 = 1000;
+const CHALLENGE_ACK_PERIOD: Duration = Duration::from_secs(1);
+
+//the RFC 5961 checks a segment can fall foul of
+#[derive(Debug, Clone, Copy)]
+pub enum Mitigation {
+    //a reset outside the receive window, dropped
+    ResetOutOfWindow,
+    //a reset inside the window that wasn't exactly at rcv.nxt, answered with a challenge ACK
+    ResetChallenged,
+    //a SYN on a synchronized connection, answered with a challenge ACK
+    SynChallenged,
+    //an ACK for data we never sent or acknowledged long ago, dropped with a challenge ACK
+    AcknowledgementOutOfRange,
+    //a challenge ACK held back by the global limit
+    ChallengeAckRateLimited,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MitigationCounters {
+    pub resets_out_of_window: u64,
+    pub resets_challenged: u64,
+    pub syns_challenged: u64,
+    pub acknowledgements_out_of_range: u64,
+    pub challenge_acks_rate_limited: u64,
+}
+
+impl MitigationCounters {
+
+    fn record(&mut self, mitigation: Mitigation) -> u64 {
+        let counter = match mitigation {
+            Mitigation::ResetOutOfWindow => &mut self.resets_out_of_window,
+            Mitigation::ResetChallenged => &mut self.resets_challenged,
+            Mitigation::SynChallenged => &mut self.syns_challenged,
+            Mitigation::AcknowledgementOutOfRange => &mut self.acknowledgements_out_of_range,
+            Mitigation::ChallengeAckRateLimited => &mut self.challenge_acks_rate_limited,
+        };
+        *counter += 1;
+        *counter
+    }
+}
+
+#[derive(Default)]
+struct ChallengeAckLimiter {
+    period_start: Option<Instant>,
+    //challenge ACKs asked for this period, including the ones held back
+    sent: u32,
+}
+
+//what the limiter made of one more challenge ACK
+#[derive(Debug, PartialEq)]
+enum ChallengeAck {
+    Allowed,
+    //held back, and the first one this period to be
+    LimitReached,
+    Limited,
+}
+
+impl ChallengeAckLimiter {
+
+    fn check(&mut self, now: Instant) -> ChallengeAck {
+        let period_over = self.period_start.is_none_or(|start| now.saturating_duration_since(start) >= CHALLENGE_ACK_PERIOD);
+        if period_over {
+            self.period_start = Some(now);
+            self.sent = 0;
+        }
+        self.sent = self.sent.saturating_add(1);
+        match self.sent {
+            sent if sent <= CHALLENGE_ACK_LIMIT => ChallengeAck::Allowed,
+            sent if sent == CHALLENGE_ACK_LIMIT + 1 => ChallengeAck::LimitReached,
+            _ => ChallengeAck::Limited,
+        }
+    }
+}
+
+lazy_static! {
+    static ref COUNTERS: Mutex<MitigationCounters> = Mutex::new(MitigationCounters::default());
+    static ref CHALLENGE_ACK_LIMITER: Mutex<ChallengeAckLimiter> = Mutex::new(ChallengeAckLimiter::default());
+}
+
+//counts one more segment caught by mitigation, returns how many have been so far
+pub fn record(mitigation: Mitigation) -> u64 {
+    COUNTERS.lock().unwrap().record(mitigation)
+}
+
+//a snapshot of every counter, for the control plane's Stats request
+pub fn counters() -> MitigationCounters {
+    *COUNTERS.lock().unwrap()
+}
+
+//whether one more challenge ACK may go out in the current period
+pub fn challenge_ack_allowed(now: Instant) -> bool {
+    let outcome = CHALLENGE_ACK_LIMITER.lock().unwrap().check(now);
+    if outcome == ChallengeAck::Allowed {
+        return true;
+    }
+    record(Mitigation::ChallengeAckRateLimited);
+    //once per period is enough to show someone is hammering us
+    if outcome == ChallengeAck::LimitReached {
+        println!("[INFO]: challenge ACK limit reached, {:?}", counters());
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{ChallengeAck, ChallengeAckLimiter, Mitigation, MitigationCounters, CHALLENGE_ACK_LIMIT, CHALLENGE_ACK_PERIOD};
+
+    //fresh counters and limiter in each test rather than the shared ones, so nothing depends on
+    //what other tests have done to them
+
+    #[test]
+    fn record_counts_each_mitigation_on_its_own() {
+        let mut counters = MitigationCounters::default();
+        assert_eq!(counters.record(Mitigation::SynChallenged), 1);
+        counters.record(Mitigation::ResetChallenged);
+        assert_eq!(counters.record(Mitigation::ResetChallenged), 2);
+        assert_eq!(counters.syns_challenged, 1);
+        assert_eq!(counters.resets_challenged, 2);
+        assert_eq!(counters.resets_out_of_window, 0);
+        assert_eq!(counters.acknowledgements_out_of_range, 0);
+        assert_eq!(counters.challenge_acks_rate_limited, 0);
+    }
+
+    #[test]
+    fn challenge_acks_are_capped_per_period() {
+        let mut limiter = ChallengeAckLimiter::default();
+        let now = Instant::now();
+        for _ in 0..CHALLENGE_ACK_LIMIT {
+            assert_eq!(limiter.check(now), ChallengeAck::Allowed);
+        }
+        //only the first one over the limit is reported as reaching it
+        assert_eq!(limiter.check(now), ChallengeAck::LimitReached);
+        assert_eq!(limiter.check(now), ChallengeAck::Limited);
+        assert_eq!(limiter.check(now + CHALLENGE_ACK_PERIOD - Duration::from_millis(1)), ChallengeAck::Limited);
+
+        //a new period starts over, and can reach the limit again
+        let next_period = now + CHALLENGE_ACK_PERIOD;
+        for _ in 0..CHALLENGE_ACK_LIMIT {
+            assert_eq!(limiter.check(next_period), ChallengeAck::Allowed);
+        }
+        assert_eq!(limiter.check(next_period), ChallengeAck::LimitReached);
+    }
+}
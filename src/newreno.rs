@@ -0,0 +1,77 @@
+use std::time::Instant;
+use crate::congestion::{initial_window, loss_threshold, AckEvent, CongestionControl};
+
+//RFC 5681 slow start and congestion avoidance with the RFC 6582 NewReno exit from fast recovery.
+//Congestion avoidance uses appropriate byte counting (RFC 3465), one MSS per window of data acked.
+pub struct NewReno {
+    maximum_segment_size: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    bytes_acknowledged: usize,
+}
+
+impl NewReno {
+
+    pub fn new(maximum_segment_size: usize) -> Self {
+        NewReno {
+            maximum_segment_size,
+            cwnd: initial_window(maximum_segment_size),
+            ssthresh: usize::MAX,
+            bytes_acknowledged: 0,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+
+    fn name(&self) -> &'static str {
+        "newreno"
+    }
+
+    fn set_maximum_segment_size(&mut self, maximum_segment_size: usize) {
+        self.maximum_segment_size = maximum_segment_size;
+        self.cwnd = initial_window(maximum_segment_size);
+    }
+
+    fn on_ack(&mut self, event: &AckEvent) {
+        if event.in_recovery {
+            return;
+        }
+        if self.cwnd < self.ssthresh {
+            //slow start, RFC 5681 caps the increase per ACK at one MSS
+            self.cwnd += event.acknowledged_bytes.min(self.maximum_segment_size);
+            return;
+        }
+        self.bytes_acknowledged += event.acknowledged_bytes;
+        if self.bytes_acknowledged >= self.cwnd {
+            self.bytes_acknowledged -= self.cwnd;
+            self.cwnd += self.maximum_segment_size;
+        }
+    }
+
+    fn on_loss(&mut self, bytes_in_flight: usize, _now: Instant) {
+        self.ssthresh = loss_threshold(bytes_in_flight, self.maximum_segment_size);
+        self.cwnd = self.ssthresh;
+        self.bytes_acknowledged = 0;
+    }
+
+    //RFC 6582 section 3.2 step 3, option 1: deflate to ssthresh but don't allow a burst
+    fn on_recovery_end(&mut self, bytes_in_flight: usize, _now: Instant) {
+        self.cwnd = self.ssthresh.min(bytes_in_flight.max(self.maximum_segment_size) + self.maximum_segment_size);
+    }
+
+    fn on_rto(&mut self, bytes_in_flight: usize, _now: Instant) {
+        self.ssthresh = loss_threshold(bytes_in_flight, self.maximum_segment_size);
+        //the loss window, one segment
+        self.cwnd = self.maximum_segment_size;
+        self.bytes_acknowledged = 0;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+}
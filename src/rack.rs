@@ -0,0 +1,219 @@
+use std::time::{Duration, Instant};
+use crate::retransmission::{SentSegment, DUPLICATE_THRESHOLD};
+use crate::seqnum::SeqNum;
+
+//RFC 8985 section 6.2, the reordering window grows by a quarter of min RTT per D-SACK round and
+//is put back after this many recoveries without one
+const MAXIMUM_REORDERING_WINDOW_MULTIPLIER: u32 = 8;
+const REORDERING_WINDOW_PERSIST: u32 = 16;
+
+//RACK, RFC 8985. A segment is lost once something sent after it has been delivered and it still
+//hasn't been after an RTT plus a reordering window, however many segments made it past it.
+pub struct Rack {
+    //send time and end of the most recently sent segment known to have been delivered
+    transmit_time: Option<Instant>,
+    end_sequence_number: SeqNum,
+    //RTT measured off that segment
+    rtt: Duration,
+    min_rtt: Option<Duration>,
+    //RACK.fack, the highest end of anything delivered
+    forward_acknowledgement: Option<SeqNum>,
+    reordering_seen: bool,
+    reordering_window_multiplier: u32,
+    reordering_window_persist: u32,
+    //snd.nxt when the multiplier last went up, one increase per round trip
+    dsack_round: Option<SeqNum>,
+}
+
+impl Default for Rack {
+    fn default() -> Self {
+        Rack {
+            transmit_time: None,
+            end_sequence_number: SeqNum::default(),
+            rtt: Duration::ZERO,
+            min_rtt: None,
+            forward_acknowledgement: None,
+            reordering_seen: false,
+            reordering_window_multiplier: 1,
+            reordering_window_persist: REORDERING_WINDOW_PERSIST,
+            dsack_round: None,
+        }
+    }
+}
+
+impl Rack {
+
+    //steps 1 to 3, for every segment an ACK newly acknowledges or SACKs
+    pub fn on_delivered(&mut self, segment: &SentSegment, now: Instant) {
+        let rtt = now.saturating_duration_since(segment.last_sent);
+        //a retransmission acknowledged quicker than any RTT seen was really the original arriving
+        if segment.transmissions > 1 && self.min_rtt.is_some_and(|min_rtt| rtt < min_rtt) {
+            return;
+        }
+        self.min_rtt = Some(self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt)));
+
+        let end = segment.end_sequence_number();
+        let newer = match self.transmit_time {
+            None => true,
+            Some(transmit_time) => segment.last_sent > transmit_time
+                || (segment.last_sent == transmit_time && end.gt(self.end_sequence_number)),
+        };
+        if newer {
+            self.transmit_time = Some(segment.last_sent);
+            self.end_sequence_number = end;
+            self.rtt = rtt;
+        }
+
+        //something further along got there first without this one ever being resent
+        match self.forward_acknowledgement {
+            Some(forward_acknowledgement) if end.lt(forward_acknowledgement) => {
+                if segment.transmissions == 1 {
+                    self.reordering_seen = true;
+                }
+            },
+            _ => self.forward_acknowledgement = Some(end),
+        }
+    }
+
+    //whether segment went out before the most recently sent one known delivered
+    pub fn sent_before_delivered(&self, segment: &SentSegment) -> bool {
+        match self.transmit_time {
+            None => false,
+            Some(transmit_time) => segment.last_sent < transmit_time
+                || (segment.last_sent == transmit_time && segment.end_sequence_number().lt(self.end_sequence_number)),
+        }
+    }
+
+    //how long a segment is due past the RTT before it counts as lost
+    pub fn reordering_window(&self, in_recovery: bool, sacked_segments: usize, smoothed_rtt: Option<Duration>) -> Duration {
+        //until the path has been seen to reorder, behave like the duplicate threshold would
+        if !self.reordering_seen && (in_recovery || sacked_segments >= DUPLICATE_THRESHOLD) {
+            return Duration::ZERO;
+        }
+        let window = self.min_rtt.unwrap_or_default() * self.reordering_window_multiplier / 4;
+        match smoothed_rtt {
+            Some(smoothed_rtt) => window.min(smoothed_rtt),
+            None => window,
+        }
+    }
+
+    //time a segment sent at last_sent still has before it is declared lost, zero once it is
+    pub fn remaining(&self, last_sent: Instant, reordering_window: Duration, now: Instant) -> Duration {
+        (last_sent + self.rtt + reordering_window).saturating_duration_since(now)
+    }
+
+    //a D-SACK says a retransmission was spurious, give reordering more room
+    pub fn on_dsack(&mut self, send_next: SeqNum, send_unacknowledged: SeqNum) {
+        if self.dsack_round.is_some_and(|round| send_unacknowledged.lt(round)) {
+            return;
+        }
+        self.dsack_round = Some(send_next);
+        self.reordering_window_multiplier = (self.reordering_window_multiplier + 1).min(MAXIMUM_REORDERING_WINDOW_MULTIPLIER);
+        self.reordering_window_persist = REORDERING_WINDOW_PERSIST;
+    }
+
+    pub fn on_recovery_end(&mut self) {
+        self.reordering_window_persist = self.reordering_window_persist.saturating_sub(1);
+        if self.reordering_window_persist == 0 {
+            self.reordering_window_multiplier = 1;
+            self.reordering_window_persist = REORDERING_WINDOW_PERSIST;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::delivery::DeliveryRateEstimator;
+    use crate::retransmission::RetransmissionQueue;
+    use crate::seqnum::SeqNum;
+
+    const SEGMENT: u32 = 100;
+    const RTT: Duration = Duration::from_millis(100);
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    //segments of SEGMENT bytes from sequence number 0, the nth sent at start plus send_offsets[n]
+    fn queue_sent_at(start: Instant, send_offsets: &[u64]) -> (RetransmissionQueue, DeliveryRateEstimator) {
+        let mut delivery = DeliveryRateEstimator::new(start);
+        let mut queue = RetransmissionQueue::default();
+        for (index, offset) in send_offsets.iter().enumerate() {
+            let sent_at = start + millis(*offset);
+            let snapshot = delivery.on_send(index * SEGMENT as usize, sent_at);
+            queue.push(SeqNum::new(index as u32 * SEGMENT), SEGMENT, false, false, snapshot, sent_at);
+        }
+        (queue, delivery)
+    }
+
+    fn sack(queue: &mut RetransmissionQueue, delivery: &mut DeliveryRateEstimator, index: u32, now: Instant) {
+        let block = (SeqNum::new(index * SEGMENT), SeqNum::new((index + 1) * SEGMENT));
+        assert!(queue.apply_sack(&[block], delivery, now));
+    }
+
+    //RFC 8985 section 6.2, the first segment is only lost once the one sent after it has been
+    //delivered and a whole RTT plus the reordering window of min_rtt / 4 have passed since it was sent
+    #[test]
+    fn lost_only_after_the_rtt_and_the_reordering_window() {
+        let start = Instant::now();
+        let (mut queue, mut delivery) = queue_sent_at(start, &[0, 10]);
+        sack(&mut queue, &mut delivery, 1, start + millis(10) + RTT);
+
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + millis(110)), (false, Some(millis(15))));
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + millis(124)), (false, Some(millis(1))));
+        assert!(!queue.has_lost());
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + millis(125)), (true, None));
+        assert!(queue.front().unwrap().lost);
+    }
+
+    //until something sent later gets there RACK has nothing to compare against, that's for the RTO
+    #[test]
+    fn nothing_is_lost_before_a_later_segment_is_delivered() {
+        let start = Instant::now();
+        let (mut queue, _) = queue_sent_at(start, &[0, 10]);
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + Duration::from_secs(10)), (false, None));
+        assert!(!queue.has_lost());
+    }
+
+    //before any reordering has been seen, DupThresh SACKed segments or being in recovery already
+    //means loss and the window shrinks to nothing
+    #[test]
+    fn no_reordering_window_once_dupthresh_is_reached() {
+        let start = Instant::now();
+        let (mut queue, mut delivery) = queue_sent_at(start, &[0, 1, 2, 3]);
+        sack(&mut queue, &mut delivery, 1, start + millis(101));
+        sack(&mut queue, &mut delivery, 2, start + millis(102));
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + millis(102)), (false, Some(millis(23))));
+        assert_eq!(queue.detect_losses(true, Some(RTT), start + millis(102)), (true, None));
+
+        let (mut queue, mut delivery) = queue_sent_at(start, &[0, 1, 2, 3]);
+        for index in 1..=3 {
+            sack(&mut queue, &mut delivery, index, start + millis(100 + index as u64));
+        }
+        assert_eq!(queue.detect_losses(false, Some(RTT), start + millis(103)), (true, None));
+    }
+
+    //once the path is known to reorder the window stays, and every D-SACK round adds a quarter
+    //of min_rtt to it
+    #[test]
+    fn reordering_and_dsacks_widen_the_window() {
+        let start = Instant::now();
+        let (mut queue, mut delivery) = queue_sent_at(start, &[0, 1, 2, 3, 4]);
+        for index in 1..=3 {
+            sack(&mut queue, &mut delivery, index, start + millis(100 + index as u64));
+        }
+        //the first one turns up after all, without ever being resent
+        queue.acknowledge(SeqNum::new(SEGMENT), &mut delivery, start + millis(104));
+        let rack = queue.rack_mut();
+        assert_eq!(rack.reordering_window(true, 3, Some(RTT)), millis(25));
+
+        rack.on_dsack(SeqNum::new(5 * SEGMENT), SeqNum::new(SEGMENT));
+        assert_eq!(rack.reordering_window(true, 3, Some(RTT)), millis(50));
+        //only one increase per round trip
+        rack.on_dsack(SeqNum::new(5 * SEGMENT), SeqNum::new(2 * SEGMENT));
+        assert_eq!(rack.reordering_window(true, 3, Some(RTT)), millis(50));
+        //never more than the smoothed RTT
+        assert_eq!(rack.reordering_window(true, 3, Some(millis(40))), millis(40));
+    }
+}
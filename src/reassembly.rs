@@ -0,0 +1,239 @@
+use crate::seqnum::SeqNum;
+
+//a peer spraying tiny segments with gaps between them shouldn't be able to make us keep an
+//unbounded number of separate ranges, the ones furthest out get dropped past this
+const MAXIMUM_RANGES: usize = 64;
+
+//data that arrived ahead of rcv.nxt, waiting for the gap in front of it to be filled. Ranges are
+//kept sorted, never overlap and never touch, anything adjacent gets merged on the way in.
+#[derive(Default)]
+pub struct ReassemblyQueue {
+    ranges: Vec<(SeqNum, Vec<u8>)>,
+    //sequence number of a FIN that came in ahead of the data before it
+    fin: Option<SeqNum>,
+    //start of the most recent segment stored, its range goes first in our SACK blocks
+    last_inserted: Option<SeqNum>,
+}
+
+impl ReassemblyQueue {
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    //start and end of each range held, in sequence order
+    pub fn ranges(&self) -> impl Iterator<Item = (SeqNum, SeqNum)> + '_ {
+        self.ranges.iter().map(|(start, data)| (*start, *start + data.len() as u32))
+    }
+
+    //store a segment that starts beyond receive_next. Only what falls inside the receive window is
+    //kept, so the queue can never hold more than we advertised room for.
+    pub fn insert(&mut self, receive_next: SeqNum, window: u32, sequence_number: SeqNum, data: &[u8]) {
+        let window_end = receive_next + window;
+        let mut start = sequence_number;
+        let mut data = data;
+        if start.lt(receive_next) {
+            let already_received = (receive_next - start) as usize;
+            if already_received >= data.len() {
+                return;
+            }
+            data = &data[already_received..];
+            start = receive_next;
+        }
+        if !start.lt(window_end) {
+            return;
+        }
+        data = &data[..data.len().min((window_end - start) as usize)];
+        if data.is_empty() {
+            return;
+        }
+
+        //offsets from receive_next are all inside the window, so they order correctly as plain integers
+        let offset_of = |sequence_number: SeqNum| sequence_number - receive_next;
+        let mut merged_start = offset_of(start);
+        let mut merged_end = merged_start + data.len() as u32;
+
+        //every held range that overlaps or touches the new one gets folded into it
+        let first = self.ranges.iter().position(|(range_start, range_data)| {
+            offset_of(*range_start) + range_data.len() as u32 >= merged_start
+        }).unwrap_or(self.ranges.len());
+        let mut last = first;
+        while last < self.ranges.len() && offset_of(self.ranges[last].0) <= merged_end {
+            last += 1;
+        }
+        for (range_start, range_data) in &self.ranges[first..last] {
+            merged_start = merged_start.min(offset_of(*range_start));
+            merged_end = merged_end.max(offset_of(*range_start) + range_data.len() as u32);
+        }
+
+        let mut merged = vec![0u8; (merged_end - merged_start) as usize];
+        for (range_start, range_data) in self.ranges.drain(first..last) {
+            let at = (offset_of(range_start) - merged_start) as usize;
+            merged[at..at + range_data.len()].copy_from_slice(&range_data);
+        }
+        let at = (offset_of(start) - merged_start) as usize;
+        merged[at..at + data.len()].copy_from_slice(data);
+        self.ranges.insert(first, (receive_next + merged_start, merged));
+        self.last_inserted = Some(start);
+
+        self.ranges.truncate(MAXIMUM_RANGES);
+    }
+
+    //hands back the data at the front once receive_next has caught up with it
+    pub fn pop_contiguous(&mut self, receive_next: SeqNum) -> Option<Vec<u8>> {
+        while let Some((start, _)) = self.ranges.first() {
+            if start.gt(receive_next) {
+                return None;
+            }
+            let (start, mut data) = self.ranges.remove(0);
+            //in order data may have overtaken part of the range in the meantime
+            let already_received = (receive_next - start) as usize;
+            if already_received < data.len() {
+                data.drain(..already_received);
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    //RFC 2018 section 4, the block holding the segment that arrived last comes first, then the rest
+    pub fn sack_blocks(&self, maximum_blocks: usize) -> Vec<(SeqNum, SeqNum)> {
+        let mut blocks: Vec<(SeqNum, SeqNum)> = self.ranges().collect();
+        if let Some(last_inserted) = self.last_inserted {
+            if let Some(position) = blocks.iter().position(|(start, end)| last_inserted.between(*start, *end)) {
+                let recent = blocks.remove(position);
+                blocks.insert(0, recent);
+            }
+        }
+        blocks.truncate(maximum_blocks);
+        blocks
+    }
+
+    pub fn set_fin(&mut self, sequence_number: SeqNum) {
+        self.fin = Some(sequence_number);
+    }
+
+    //true once everything before a FIN we held on to has arrived
+    pub fn take_fin(&mut self, receive_next: SeqNum) -> bool {
+        if self.fin == Some(receive_next) {
+            self.fin = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.fin = None;
+        self.last_inserted = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReassemblyQueue, MAXIMUM_RANGES};
+    use crate::seqnum::SeqNum;
+
+    const RECEIVE_NEXT: u32 = 1000;
+    const WINDOW: u32 = 65535;
+
+    //the bytes from start up to end, each one tells where in the stream it came from
+    fn text(start: u32, end: u32) -> Vec<u8> {
+        (start..end).map(|sequence_number| sequence_number as u8).collect()
+    }
+
+    fn insert(queue: &mut ReassemblyQueue, start: u32, end: u32) {
+        queue.insert(SeqNum::new(RECEIVE_NEXT), WINDOW, SeqNum::new(start), &text(start, end));
+    }
+
+    fn ranges(queue: &ReassemblyQueue) -> Vec<(u32, u32)> {
+        queue.ranges().map(|(start, end)| (start.value(), end.value())).collect()
+    }
+
+    #[test]
+    fn overlaps_and_duplicates_are_trimmed() {
+        let mut queue = ReassemblyQueue::default();
+        insert(&mut queue, 1100, 1110);
+        insert(&mut queue, 1100, 1110);
+        assert_eq!(ranges(&queue), vec![(1100, 1110)]);
+        insert(&mut queue, 1105, 1120);
+        insert(&mut queue, 1090, 1102);
+        assert_eq!(ranges(&queue), vec![(1090, 1120)]);
+        //anything before rcv.nxt is already ours
+        insert(&mut queue, 990, 1010);
+        assert_eq!(ranges(&queue), vec![(1000, 1010), (1090, 1120)]);
+
+        assert_eq!(queue.pop_contiguous(SeqNum::new(RECEIVE_NEXT)), Some(text(1000, 1010)));
+        assert_eq!(queue.pop_contiguous(SeqNum::new(1010)), None);
+        //in order data overtook the front of the range in the meantime
+        assert_eq!(queue.pop_contiguous(SeqNum::new(1095)), Some(text(1095, 1120)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn adjacent_ranges_are_coalesced() {
+        let mut queue = ReassemblyQueue::default();
+        insert(&mut queue, 1100, 1110);
+        insert(&mut queue, 1120, 1130);
+        assert_eq!(ranges(&queue), vec![(1100, 1110), (1120, 1130)]);
+        insert(&mut queue, 1110, 1120);
+        assert_eq!(ranges(&queue), vec![(1100, 1130)]);
+        insert(&mut queue, 1130, 1140);
+        assert_eq!(ranges(&queue), vec![(1100, 1140)]);
+        assert_eq!(queue.pop_contiguous(SeqNum::new(1100)), Some(text(1100, 1140)));
+    }
+
+    //the queue never holds more than the window we advertised
+    #[test]
+    fn only_what_fits_in_the_receive_window_is_kept() {
+        let mut queue = ReassemblyQueue::default();
+        let window = 100;
+        queue.insert(SeqNum::new(RECEIVE_NEXT), window, SeqNum::new(1050), &text(1050, 1200));
+        assert_eq!(ranges(&queue), vec![(1050, 1100)]);
+        queue.insert(SeqNum::new(RECEIVE_NEXT), window, SeqNum::new(1100), &text(1100, 1110));
+        assert_eq!(ranges(&queue), vec![(1050, 1100)]);
+    }
+
+    #[test]
+    fn the_ranges_furthest_out_are_dropped_past_the_limit() {
+        let mut queue = ReassemblyQueue::default();
+        //from the far end in, one byte each with a gap between them
+        for index in (0..MAXIMUM_RANGES as u32 + 6).rev() {
+            let start = RECEIVE_NEXT + 10 + 2 * index;
+            insert(&mut queue, start, start + 1);
+        }
+        let held = ranges(&queue);
+        assert_eq!(held.len(), MAXIMUM_RANGES);
+        assert_eq!(held[0], (1010, 1011));
+        let last = RECEIVE_NEXT + 10 + 2 * (MAXIMUM_RANGES as u32 - 1);
+        assert_eq!(held[MAXIMUM_RANGES - 1], (last, last + 1));
+    }
+
+    #[test]
+    fn a_fin_after_out_of_order_data_waits_for_the_gap() {
+        let mut queue = ReassemblyQueue::default();
+        insert(&mut queue, 1100, 1110);
+        queue.set_fin(SeqNum::new(1110));
+        assert!(!queue.take_fin(SeqNum::new(RECEIVE_NEXT)));
+
+        assert_eq!(queue.pop_contiguous(SeqNum::new(1100)), Some(text(1100, 1110)));
+        assert!(queue.take_fin(SeqNum::new(1110)));
+        assert!(!queue.take_fin(SeqNum::new(1110)));
+    }
+
+    #[test]
+    fn ranges_can_straddle_the_wrap() {
+        let mut queue = ReassemblyQueue::default();
+        let receive_next = SeqNum::new(u32::MAX - 50);
+        let start = u32::MAX - 10;
+        let data: Vec<u8> = (0..30).collect();
+        queue.insert(receive_next, WINDOW, SeqNum::new(start), &data[..20]);
+        //the second half starts past zero and touches the first
+        queue.insert(receive_next, WINDOW, SeqNum::new(start.wrapping_add(20)), &data[20..]);
+        queue.insert(receive_next, WINDOW, SeqNum::new(100), &data[..5]);
+        assert_eq!(ranges(&queue), vec![(start, 19), (100, 105)]);
+
+        assert_eq!(queue.pop_contiguous(SeqNum::new(start)), Some(data.clone()));
+        assert_eq!(queue.pop_contiguous(SeqNum::new(19)), None);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::delivery::{DeliveryRateEstimator, DeliverySnapshot};
+use crate::rack::Rack;
+use crate::seqnum::SeqNum;
+
+//RFC 6298 section 2, the RTO before any measurement has been made
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MINIMUM_RTO: Duration = Duration::from_secs(1);
+const MAXIMUM_RTO: Duration = Duration::from_secs(60);
+//clock granularity G, our timers are driven off Instant so this is effectively nothing
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(1);
+//RFC 5681 and 6675 DupThresh, duplicate ACKs or SACKed segments before loss is assumed
+pub const DUPLICATE_THRESHOLD: usize = 3;
+
+//a segment that consumed sequence space and has not been fully acknowledged yet. The data itself
+//stays in the connection's outbound buffer, only where it sits in sequence space is kept here.
+#[derive(Debug, Clone)]
+pub struct SentSegment {
+    pub sequence_number: SeqNum,
+    //sequence space used, SYN and FIN count as one each
+    pub length: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub first_sent: Instant,
+    pub last_sent: Instant,
+    pub transmissions: u32,
+    //the peer has reported holding this one in a SACK block
+    pub sacked: bool,
+    //loss detection gave up on the latest transmission, it is due to be sent again
+    pub lost: bool,
+    //delivery counters as of the latest transmission, for the rate sample its ACK produces
+    pub delivery: DeliverySnapshot,
+}
+
+impl SentSegment {
+    pub fn end_sequence_number(&self) -> SeqNum {
+        self.sequence_number + self.length
+    }
+}
+
+//the sender's scoreboard: everything in flight, what the peer has SACKed and what has been given
+//up on. RACK state lives here too as it is fed by every segment that gets delivered.
+#[derive(Default)]
+pub struct RetransmissionQueue {
+    segments: VecDeque<SentSegment>,
+    rack: Rack,
+}
+
+impl RetransmissionQueue {
+
+    pub fn push(&mut self, sequence_number: SeqNum, length: u32, syn: bool, fin: bool, delivery: DeliverySnapshot, now: Instant) {
+        self.segments.push_back(SentSegment {
+            sequence_number,
+            length,
+            syn,
+            fin,
+            first_sent: now,
+            last_sent: now,
+            transmissions: 1,
+            sacked: false,
+            lost: false,
+            delivery,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn front(&self) -> Option<&SentSegment> {
+        self.segments.front()
+    }
+
+    pub fn back(&self) -> Option<&SentSegment> {
+        self.segments.back()
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    pub fn rack_mut(&mut self) -> &mut Rack {
+        &mut self.rack
+    }
+
+    //marks the earliest segment as sent again and hands it back so it can be rebuilt
+    pub fn retransmit_front(&mut self, delivery: DeliverySnapshot, now: Instant) -> Option<SentSegment> {
+        Self::retransmit(self.segments.front_mut()?, delivery, now)
+    }
+
+    //same for the latest, a tail loss probe with no new data to send
+    pub fn retransmit_back(&mut self, delivery: DeliverySnapshot, now: Instant) -> Option<SentSegment> {
+        Self::retransmit(self.segments.back_mut()?, delivery, now)
+    }
+
+    //RFC 6675 NextSeg() rule 1, the first segment marked lost. It is marked as sent again and
+    //handed back so it can be rebuilt.
+    pub fn retransmit_next_lost(&mut self, delivery: DeliverySnapshot, now: Instant) -> Option<SentSegment> {
+        let segment = self.segments.iter_mut().find(|segment| segment.lost)?;
+        Self::retransmit(segment, delivery, now)
+    }
+
+    fn retransmit(segment: &mut SentSegment, delivery: DeliverySnapshot, now: Instant) -> Option<SentSegment> {
+        segment.last_sent = now;
+        segment.transmissions += 1;
+        segment.lost = false;
+        segment.delivery = delivery;
+        Some(segment.clone())
+    }
+
+    pub fn has_lost(&self) -> bool {
+        self.segments.iter().any(|segment| segment.lost)
+    }
+
+    //duplicate ACKs or a partial ACK without SACK to go on, the earliest segment is taken as lost
+    pub fn mark_front_lost(&mut self) {
+        if let Some(segment) = self.segments.front_mut() {
+            segment.lost = !segment.sacked;
+        }
+    }
+
+    //updates the scoreboard from the SACK blocks on an ACK, true if anything new got SACKed. Only
+    //segments a block covers completely are marked.
+    pub fn apply_sack(&mut self, blocks: &[(SeqNum, SeqNum)], delivery: &mut DeliveryRateEstimator, now: Instant) -> bool {
+        let mut newly_sacked = false;
+        for segment in self.segments.iter_mut().filter(|segment| !segment.sacked) {
+            let end = segment.end_sequence_number();
+            if blocks.iter().any(|(left, right)| left.le(segment.sequence_number) && end.le(*right)) {
+                segment.sacked = true;
+                segment.lost = false;
+                newly_sacked = true;
+                delivery.on_delivered(&segment.delivery, segment.length, segment.last_sent, now);
+                self.rack.on_delivered(segment, now);
+            }
+        }
+        newly_sacked
+    }
+
+    //RFC 8985 section 6.2 step 5, marks everything RACK now considers lost. Returns whether anything
+    //new was, and how long until the next segment that isn't yet would be.
+    pub fn detect_losses(&mut self, in_recovery: bool, smoothed_rtt: Option<Duration>, now: Instant) -> (bool, Option<Duration>) {
+        let sacked_segments = self.segments.iter().filter(|segment| segment.sacked).count();
+        let reordering_window = self.rack.reordering_window(in_recovery, sacked_segments, smoothed_rtt);
+        let mut newly_lost = false;
+        let mut timeout: Option<Duration> = None;
+        for segment in self.segments.iter_mut().filter(|segment| !segment.sacked && !segment.lost) {
+            if !self.rack.sent_before_delivered(segment) {
+                continue;
+            }
+            let remaining = self.rack.remaining(segment.last_sent, reordering_window, now);
+            if remaining.is_zero() {
+                segment.lost = true;
+                newly_lost = true;
+            } else {
+                timeout = Some(timeout.map_or(remaining, |timeout| timeout.max(remaining)));
+            }
+        }
+        (newly_lost, timeout)
+    }
+
+    //RFC 8985 section 6.3, on an RTO the earliest segment and anything that has been out for longer
+    //than RACK would wait are lost
+    pub fn mark_losses_on_timeout(&mut self, smoothed_rtt: Option<Duration>, now: Instant) {
+        let reordering_window = self.rack.reordering_window(true, 0, smoothed_rtt);
+        for (index, segment) in self.segments.iter_mut().enumerate().filter(|(_, segment)| !segment.sacked) {
+            if index == 0 || self.rack.remaining(segment.last_sent, reordering_window, now).is_zero() {
+                segment.lost = true;
+            }
+        }
+    }
+
+    //RFC 6675 SetPipe(), an estimate of how many bytes are still in the network: everything not
+    //SACKed and not given up on. A lost segment that has been resent is back in the network.
+    pub fn pipe(&self) -> usize {
+        self.segments.iter()
+            .filter(|segment| !segment.sacked && !segment.lost)
+            .map(|segment| segment.length as usize)
+            .sum()
+    }
+
+    //drops everything covered by a cumulative ACK and returns an RTT sample if one may be taken.
+    //Per Karn's algorithm only segments that were never retransmitted are measured.
+    pub fn acknowledge(&mut self, acknowledgement_number: SeqNum, delivery: &mut DeliveryRateEstimator, now: Instant) -> Option<Duration> {
+        let mut rtt_sample = None;
+        while let Some(segment) = self.segments.front_mut() {
+            let end = segment.end_sequence_number();
+            if end.le(acknowledgement_number) {
+                if segment.transmissions == 1 {
+                    rtt_sample = Some(now.duration_since(segment.first_sent));
+                }
+                //already counted when it was SACKed
+                if !segment.sacked {
+                    delivery.on_delivered(&segment.delivery, segment.length, segment.last_sent, now);
+                    self.rack.on_delivered(segment, now);
+                }
+                self.segments.pop_front();
+            } else {
+                if acknowledgement_number.gt(segment.sequence_number) {
+                    //peer took part of this one, keep only the rest
+                    let acknowledged = acknowledgement_number - segment.sequence_number;
+                    delivery.on_delivered(&segment.delivery, acknowledged, segment.last_sent, now);
+                    segment.sequence_number = acknowledgement_number;
+                    segment.length -= acknowledged;
+                    segment.syn = false;
+                }
+                break;
+            }
+        }
+        rtt_sample
+    }
+}
+
+//RFC 6298 smoothed round trip time and retransmission timeout calculation
+pub struct RtoEstimator {
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    rto: Duration,
+}
+
+impl Default for RtoEstimator {
+    fn default() -> Self {
+        RtoEstimator {
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+}
+
+impl RtoEstimator {
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub fn smoothed_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    pub fn add_sample(&mut self, rtt: Duration) {
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            },
+            Some(smoothed_rtt) => {
+                //alpha = 1/8, beta = 1/4
+                let difference = smoothed_rtt.abs_diff(rtt);
+                self.rtt_variance = (self.rtt_variance * 3 + difference) / 4;
+                self.smoothed_rtt = Some((smoothed_rtt * 7 + rtt) / 8);
+            }
+        }
+        let smoothed_rtt = self.smoothed_rtt.unwrap_or(rtt);
+        self.rto = (smoothed_rtt + CLOCK_GRANULARITY.max(self.rtt_variance * 4)).clamp(MINIMUM_RTO, MAXIMUM_RTO);
+    }
+
+    //section 5.5, back the timer off after it expires
+    pub fn back_off(&mut self) {
+        self.rto = (self.rto * 2).min(MAXIMUM_RTO);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{RetransmissionQueue, RtoEstimator, MAXIMUM_RTO, MINIMUM_RTO};
+    use crate::delivery::DeliveryRateEstimator;
+    use crate::seqnum::SeqNum;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    //RFC 6298 section 2.2, SRTT = R, RTTVAR = R/2, RTO = SRTT + max(G, 4 * RTTVAR)
+    #[test]
+    fn first_sample_sets_srtt_and_rttvar() {
+        let mut estimator = RtoEstimator::default();
+        assert_eq!(estimator.rto(), Duration::from_secs(1));
+        assert_eq!(estimator.smoothed_rtt(), None);
+        estimator.add_sample(millis(500));
+        assert_eq!(estimator.smoothed_rtt(), Some(millis(500)));
+        assert_eq!(estimator.rtt_variance, millis(250));
+        assert_eq!(estimator.rto(), millis(1500));
+    }
+
+    //section 2.3, RTTVAR = 3/4 RTTVAR + 1/4 |SRTT - R'| then SRTT = 7/8 SRTT + 1/8 R'
+    #[test]
+    fn later_samples_are_smoothed() {
+        let mut estimator = RtoEstimator::default();
+        estimator.add_sample(millis(400));
+        estimator.add_sample(millis(800));
+        assert_eq!(estimator.rtt_variance, millis(250));
+        assert_eq!(estimator.smoothed_rtt(), Some(millis(450)));
+        assert_eq!(estimator.rto(), millis(1450));
+    }
+
+    #[test]
+    fn rto_is_kept_between_one_second_and_a_minute() {
+        let mut estimator = RtoEstimator::default();
+        estimator.add_sample(millis(10));
+        assert_eq!(estimator.rto(), MINIMUM_RTO);
+
+        let mut estimator = RtoEstimator::default();
+        estimator.add_sample(Duration::from_secs(30));
+        assert_eq!(estimator.rto(), MAXIMUM_RTO);
+    }
+
+    //section 5.5, doubling each time up to the maximum
+    #[test]
+    fn backing_off_doubles_the_rto_up_to_the_maximum() {
+        let mut estimator = RtoEstimator::default();
+        for expected in [2, 4, 8, 16, 32, 60, 60] {
+            estimator.back_off();
+            assert_eq!(estimator.rto(), Duration::from_secs(expected));
+        }
+        //a fresh sample recomputes it from scratch
+        estimator.add_sample(millis(500));
+        assert_eq!(estimator.rto(), millis(1500));
+    }
+
+    //Karn's algorithm, an ACK for a retransmitted segment could be for either transmission so it
+    //gives no sample
+    #[test]
+    fn retransmitted_segments_give_no_rtt_sample() {
+        let start = Instant::now();
+        let mut delivery = DeliveryRateEstimator::new(start);
+        let mut queue = RetransmissionQueue::default();
+        queue.push(SeqNum::new(1000), 100, false, false, delivery.on_send(0, start), start);
+        queue.push(SeqNum::new(1100), 100, false, false, delivery.on_send(100, start + millis(10)), start + millis(10));
+
+        queue.retransmit_front(delivery.on_send(200, start + millis(1000)), start + millis(1000));
+        assert_eq!(queue.acknowledge(SeqNum::new(1100), &mut delivery, start + millis(1100)), None);
+        //a segment sent only once is measured from when it went out
+        assert_eq!(queue.acknowledge(SeqNum::new(1200), &mut delivery, start + millis(1110)), Some(millis(1100)));
+        assert!(queue.is_empty());
+    }
+}
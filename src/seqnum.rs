@@ -0,0 +1,175 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
+
+//a point in the 32 bit sequence space. Arithmetic wraps modulo 2^32 and ordering follows RFC 1982
+//serial number arithmetic, so a is before b when b is less than 2^31 ahead of it. That ordering is
+//not transitive across the whole space, which is why there is no PartialOrd.
+#[derive(Debug, Default, Clone, Copy, Hash, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct SeqNum(u32);
+
+impl SeqNum {
+
+    pub const fn new(value: u32) -> Self {
+        SeqNum(value)
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    pub fn lt(self, other: SeqNum) -> bool {
+        (self.0.wrapping_sub(other.0) as i32) < 0
+    }
+
+    pub fn le(self, other: SeqNum) -> bool {
+        self == other || self.lt(other)
+    }
+
+    pub fn gt(self, other: SeqNum) -> bool {
+        other.lt(self)
+    }
+
+    pub fn ge(self, other: SeqNum) -> bool {
+        other.le(self)
+    }
+
+    //start <= self < end, the window may straddle the wrap point
+    pub fn between(self, start: SeqNum, end: SeqNum) -> bool {
+        start.le(self) && self.lt(end)
+    }
+
+    //the later of the two
+    pub fn max(self, other: SeqNum) -> SeqNum {
+        if self.lt(other) { other } else { self }
+    }
+}
+
+impl From<u32> for SeqNum {
+    fn from(value: u32) -> Self {
+        SeqNum(value)
+    }
+}
+
+impl fmt::Display for SeqNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Add<u32> for SeqNum {
+    type Output = SeqNum;
+
+    fn add(self, length: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_add(length))
+    }
+}
+
+impl AddAssign<u32> for SeqNum {
+    fn add_assign(&mut self, length: u32) {
+        self.0 = self.0.wrapping_add(length);
+    }
+}
+
+impl Sub<u32> for SeqNum {
+    type Output = SeqNum;
+
+    fn sub(self, length: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_sub(length))
+    }
+}
+
+//how far self is ahead of an earlier sequence number
+impl Sub<SeqNum> for SeqNum {
+    type Output = u32;
+
+    fn sub(self, earlier: SeqNum) -> u32 {
+        self.0.wrapping_sub(earlier.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeqNum;
+
+    //points where plain u32 comparisons go wrong: the top of the space and half way round
+    const WRAP_POINTS: [u32; 4] = [0, u32::MAX, 1 << 31, (1 << 31) - 1];
+    const SPAN: u32 = 1024;
+
+    fn around(point: u32) -> impl Iterator<Item = SeqNum> {
+        (0..2 * SPAN).map(move |offset| SeqNum::new(point.wrapping_sub(SPAN).wrapping_add(offset)))
+    }
+
+    #[test]
+    fn add_wraps_past_the_top_of_the_space() {
+        assert_eq!(SeqNum::new(u32::MAX) + 1, SeqNum::new(0));
+        assert_eq!(SeqNum::new(u32::MAX - 9) + 20, SeqNum::new(10));
+        let mut sequence_number = SeqNum::new(u32::MAX);
+        sequence_number += 2;
+        assert_eq!(sequence_number, SeqNum::new(1));
+        for start in around(0) {
+            for length in 0..SPAN {
+                assert_eq!((start + length).value(), start.value().wrapping_add(length));
+            }
+        }
+    }
+
+    #[test]
+    fn sub_wraps_below_zero() {
+        assert_eq!(SeqNum::new(0) - 1, SeqNum::new(u32::MAX));
+        assert_eq!(SeqNum::new(5) - SeqNum::new(u32::MAX - 4), 10);
+        for start in around(0) {
+            for length in 0..SPAN {
+                assert_eq!((start + length) - start, length);
+                assert_eq!((start + length) - length, start);
+            }
+        }
+    }
+
+    #[test]
+    fn ordering_holds_across_every_wrap_point() {
+        for point in WRAP_POINTS {
+            for a in around(point) {
+                assert!(!a.lt(a));
+                assert!(a.le(a));
+                assert!(a.ge(a));
+                assert!(!a.gt(a));
+                for distance in 1..SPAN {
+                    let b = a + distance;
+                    assert!(a.lt(b), "{} < {}", a, b);
+                    assert!(a.le(b));
+                    assert!(b.gt(a));
+                    assert!(b.ge(a));
+                    assert!(!b.lt(a));
+                    assert!(!a.gt(b));
+                    assert_eq!(a.max(b), b);
+                    assert_eq!(b.max(a), b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ordering_flips_at_half_the_space() {
+        let a = SeqNum::new(u32::MAX - 10);
+        assert!(a.lt(a + ((1 << 31) - 1)));
+        //exactly 2^31 apart is left undefined by RFC 1982, so it isn't checked
+        assert!(a.gt(a + ((1 << 31) + 1)));
+    }
+
+    #[test]
+    fn between_handles_windows_straddling_the_wrap() {
+        for point in WRAP_POINTS {
+            let start = SeqNum::new(point) - 100;
+            let end = start + 200;
+            for offset in 0..200 {
+                assert!((start + offset).between(start, end));
+            }
+            assert!(!end.between(start, end));
+            assert!(!(start - 1).between(start, end));
+            assert!(!(end + 1000).between(start, end));
+            //an empty window holds nothing
+            assert!(!start.between(start, start));
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use tun_tap::Iface;
+use crate::congestion::CongestionAlgorithm;
+use crate::connections::{Connection, ConnectionEvent, SocketPair, DEFAULT_DELAYED_ACKNOWLEDGEMENT_TIMEOUT, DEFAULT_RECEIVE_BUFFER_SIZE, DEFAULT_TIME_WAIT_DURATION};
+use crate::events::{CommandReceiver, SocketCommand};
+use crate::ipv4::Ipv4;
+use crate::isn::IsnGenerator;
+use crate::listener::Listener;
+use crate::seqnum::SeqNum;
+use crate::syncookies;
+use crate::tcp::Tcp;
+use crate::timer::{TimerId, TimerKind, TimerWheel};
+use crate::unixsocket::UnixSocketManager;
+
+//MTU of the tun device, every segment we build has to fit in one of these
+pub const MTU: usize = 1500;
+const TUN_BUFFER_SIZE: usize = MTU + 4; // MTU + 4 for the header
+const DEFAULT_SYN_COOKIE_THRESHOLD: usize = 256;
+
+//stack wide defaults every new connection starts out with, individual sockets may override them
+#[derive(Clone, Copy)]
+pub struct StackConfig {
+    pub receive_buffer_size: usize,
+    pub congestion_algorithm: CongestionAlgorithm,
+    pub delayed_acknowledgement_timeout: Duration,
+    //2MSL, and whether a new SYN may take over a connection still in TIME_WAIT
+    pub time_wait_duration: Duration,
+    pub time_wait_reuse: bool,
+    //half-open passive connections allowed before further SYNs are answered with SYN cookies
+    pub syn_cookie_threshold: usize,
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        StackConfig {
+            receive_buffer_size: DEFAULT_RECEIVE_BUFFER_SIZE,
+            congestion_algorithm: CongestionAlgorithm::default(),
+            delayed_acknowledgement_timeout: DEFAULT_DELAYED_ACKNOWLEDGEMENT_TIMEOUT,
+            time_wait_duration: DEFAULT_TIME_WAIT_DURATION,
+            time_wait_reuse: true,
+            syn_cookie_threshold: DEFAULT_SYN_COOKIE_THRESHOLD,
+        }
+    }
+}
+
+//owns everything the event loop touches: the tun device, the control plane's command queue, the
+//connection table and the timer wheel every connection's timers live in
+pub struct Stack {
+    iface: Iface,
+    commands: CommandReceiver,
+    connection_table: HashMap<SocketPair, Connection>,
+    //socket id to the connection it owns, so commands don't have to search the table
+    owned_connections: HashMap<u32, SocketPair>,
+    //receives that found nothing to read, keyed by socket id with the requested maximum length
+    pending_receives: HashMap<u32, usize>,
+    //listening sockets by local port, and the ones with a blocking accept waiting on a connection
+    listeners: HashMap<u16, Listener>,
+    pending_accepts: HashSet<u32>,
+    timer_wheel: TimerWheel<(SocketPair, TimerKind)>,
+    //which wheel entry currently backs each armed connection timer
+    armed_timers: HashMap<(SocketPair, TimerKind), TimerId>,
+    isn_generator: IsnGenerator,
+    config: StackConfig,
+    //when the last SYN cookie went out, ACKs are only checked for one while it may still be valid
+    syn_cookie_sent_at: Option<Instant>,
+}
+
+impl Stack {
+
+    pub fn new(iface: Iface, commands: CommandReceiver, isn_generator: IsnGenerator, config: StackConfig) -> io::Result<Self> {
+        iface.set_non_blocking()?;
+        Ok(Stack {
+            iface,
+            commands,
+            connection_table: HashMap::new(),
+            owned_connections: HashMap::new(),
+            pending_receives: HashMap::new(),
+            listeners: HashMap::new(),
+            pending_accepts: HashSet::new(),
+            timer_wheel: TimerWheel::new(Instant::now()),
+            armed_timers: HashMap::new(),
+            isn_generator,
+            config,
+            syn_cookie_sent_at: None,
+        })
+    }
+
+    //waits on the tun fd, the command queue and the next timer all at once and deals with
+    //whichever is ready, forever
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut buffer = [0u8; TUN_BUFFER_SIZE];
+        loop {
+            let timeout = self.timer_wheel.next_expiry()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let (tun_ready, commands_ready) = self.wait_for_events(timeout)?;
+
+            if tun_ready {
+                loop {
+                    match self.iface.recv(&mut buffer) {
+                        Ok(nbytes) => self.handle_packet(&buffer[..nbytes]),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+
+            if commands_ready {
+                for command in self.commands.drain() {
+                    self.handle_command(command);
+                }
+            }
+
+            let now = Instant::now();
+            for (socket_pair, kind) in self.timer_wheel.advance(now) {
+                self.armed_timers.remove(&(socket_pair, kind));
+                if let Some(connection) = self.connection_table.get_mut(&socket_pair) {
+                    connection.on_timer(kind, now);
+                }
+            }
+
+            self.complete_pending_receives();
+            self.flush_connections();
+            self.sync_timers();
+            self.dispatch_connection_events();
+            self.complete_pending_accepts();
+        }
+    }
+
+    fn wait_for_events(&self, timeout: Option<Duration>) -> io::Result<(bool, bool)> {
+        let mut poll_fds = [
+            libc::pollfd { fd: self.iface.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.commands.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        //round up so we never wake just short of a deadline and spin
+        let timeout_ms = timeout.map_or(-1, |timeout| {
+            timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+        let result = unsafe { libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout_ms) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Ok((false, false));
+            }
+            return Err(error);
+        }
+        Ok((poll_fds[0].revents & libc::POLLIN != 0, poll_fds[1].revents & libc::POLLIN != 0))
+    }
+
+    //mirror whatever each connection armed or cancelled into the wheel
+    fn sync_timers(&mut self) {
+        for (socket_pair, connection) in self.connection_table.iter_mut() {
+            for (kind, deadline) in connection.take_timer_changes() {
+                if let Some(timer_id) = self.armed_timers.remove(&(*socket_pair, kind)) {
+                    self.timer_wheel.cancel(timer_id);
+                }
+                if let Some(deadline) = deadline {
+                    let timer_id = self.timer_wheel.schedule(deadline, (*socket_pair, kind));
+                    self.armed_timers.insert((*socket_pair, kind), timer_id);
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: SocketCommand) {
+        match command {
+            SocketCommand::Connect { socket_id, socket_pair, options } => {
+                let mut outbound_packet_buffer = [0u8; MTU];
+                match self.connection_table.entry(socket_pair) {
+                    Entry::Occupied(_) => {
+                        eprintln!("[ERROR]: connection {:?} already exists", socket_pair);
+                        UnixSocketManager::notify_connection_event(socket_id, &ConnectionEvent::Reset);
+                    },
+                    Entry::Vacant(entry) => {
+                        println!("New Connection: {}:{} -> {}:{} [active open]",
+                            socket_pair.dest_ip, socket_pair.dest_port,
+                            socket_pair.src_ip, socket_pair.src_port);
+                        let now = Instant::now();
+                        let initial_sequence_number = self.isn_generator.generate(&socket_pair, now);
+                        let connection = entry.insert(Self::new_connection(&self.isn_generator, &self.config, socket_pair, Some(socket_id), initial_sequence_number));
+                        self.owned_connections.insert(socket_id, socket_pair);
+                        for option in options {
+                            connection.set_option(option, now);
+                        }
+                        match connection.open(now, &mut outbound_packet_buffer) {
+                            Ok(length) => Self::send_packet(&self.iface, &outbound_packet_buffer[..length]),
+                            Err(e) => eprintln!("[ERROR]: {}", e),
+                        }
+                    }
+                }
+            },
+            SocketCommand::Listen { socket_id, port, backlog, options } => {
+                match self.listeners.entry(port) {
+                    Entry::Occupied(mut entry) if entry.get().socket_id() == socket_id => {
+                        entry.get_mut().set_backlog(backlog);
+                        UnixSocketManager::complete_listen(socket_id, Ok(()));
+                    },
+                    Entry::Occupied(_) => {
+                        UnixSocketManager::complete_listen(socket_id, Err(format!("[ERROR]: port {} already has a listener", port)));
+                    },
+                    Entry::Vacant(entry) => {
+                        println!("[INFO]: listening on port {} with a backlog of {}", port, backlog);
+                        entry.insert(Listener::new(socket_id, backlog, options));
+                        UnixSocketManager::complete_listen(socket_id, Ok(()));
+                    }
+                }
+            },
+            SocketCommand::Accept { socket_id, nonblocking } => {
+                if self.accept_connection(socket_id) {
+                    return;
+                }
+                if nonblocking {
+                    UnixSocketManager::accept_would_block(socket_id);
+                } else {
+                    self.pending_accepts.insert(socket_id);
+                }
+            },
+            SocketCommand::Send { socket_id, data } => {
+                let result = match self.find_owned_connection(socket_id) {
+                    Some(connection) => connection.write(&data),
+                    None => Err("[ERROR]: connection does not exist".to_string()),
+                };
+                UnixSocketManager::complete_send(socket_id, result);
+            },
+            SocketCommand::Receive { socket_id, maximum_length } => {
+                if self.find_owned_connection(socket_id).is_none() {
+                    UnixSocketManager::complete_receive(socket_id, Err("[ERROR]: connection does not exist".to_string()));
+                    return;
+                }
+                self.pending_receives.insert(socket_id, maximum_length);
+            },
+            SocketCommand::SetOption { socket_id, option } => {
+                //the socket may already be gone, it carries the option itself for the next connect
+                if let Some(connection) = self.find_owned_connection(socket_id) {
+                    connection.set_option(option, Instant::now());
+                }
+            },
+            SocketCommand::Close { socket_id } => {
+                self.pending_receives.remove(&socket_id);
+                self.pending_accepts.remove(&socket_id);
+                self.close_listener(socket_id);
+                if let Some(connection) = self.find_owned_connection(socket_id) {
+                    if let Err(e) = connection.close() {
+                        eprintln!("[ERROR]: {}", e);
+                    }
+                }
+            },
+        }
+    }
+
+    //hands the next connection in the listener's accept queue to the client, false if there is none
+    fn accept_connection(&mut self, socket_id: u32) -> bool {
+        let Some(listener) = self.listeners.values_mut().find(|listener| listener.socket_id() == socket_id) else {
+            return false;
+        };
+        let Some(socket_pair) = listener.next_accepted() else {
+            return false;
+        };
+        let Some(new_socket_id) = UnixSocketManager::complete_accept(socket_id, socket_pair, listener.options().to_vec()) else {
+            return false;
+        };
+        println!("[INFO]: accepted {:?} as socket {}", socket_pair, new_socket_id);
+        listener.forget(&socket_pair);
+        if let Some(connection) = self.connection_table.get_mut(&socket_pair) {
+            connection.set_owner(new_socket_id);
+            self.owned_connections.insert(new_socket_id, socket_pair);
+        }
+        true
+    }
+
+    fn complete_pending_accepts(&mut self) {
+        let waiting: Vec<u32> = self.pending_accepts.iter().copied().collect();
+        for socket_id in waiting {
+            if self.accept_connection(socket_id) {
+                self.pending_accepts.remove(&socket_id);
+            }
+        }
+    }
+
+    //nobody is going to accept whatever is still queued on a closed listening socket, reset it all
+    fn close_listener(&mut self, socket_id: u32) {
+        let Some(port) = self.listeners.iter().find(|(_, listener)| listener.socket_id() == socket_id).map(|(port, _)| *port) else {
+            return;
+        };
+        let Some(mut listener) = self.listeners.remove(&port) else {
+            return;
+        };
+        println!("[INFO]: no longer listening on port {}", port);
+        let mut outbound_packet_buffer = [0u8; MTU];
+        for socket_pair in listener.take_queued() {
+            if let Some(connection) = self.connection_table.get_mut(&socket_pair) {
+                let length = connection.reset(&mut outbound_packet_buffer);
+                Self::send_packet(&self.iface, &outbound_packet_buffer[..length]);
+            }
+        }
+    }
+
+    //a fresh connection set up the way every connection on this stack starts out. Takes the pieces
+    //it needs rather than self so it can be called while the connection table is borrowed.
+    fn new_connection(isn_generator: &IsnGenerator, config: &StackConfig, socket_pair: SocketPair, owner: Option<u32>, initial_sequence_number: SeqNum) -> Connection {
+        let mut connection = match owner {
+            Some(socket_id) => Connection::new_active(socket_pair, socket_id, initial_sequence_number),
+            None => Connection::new_passive(socket_pair, initial_sequence_number),
+        };
+        if let Err(e) = connection.set_receive_buffer_size(config.receive_buffer_size) {
+            eprintln!("[ERROR]: {}", e);
+        }
+        connection.set_congestion_control(config.congestion_algorithm);
+        connection.set_delayed_acknowledgement_timeout(config.delayed_acknowledgement_timeout);
+        connection.set_time_wait_duration(config.time_wait_duration);
+        connection.set_timestamp_offset(isn_generator.timestamp_offset(&socket_pair));
+        connection
+    }
+
+    fn find_owned_connection(&mut self, socket_id: u32) -> Option<&mut Connection> {
+        let socket_pair = self.owned_connections.get(&socket_id)?;
+        self.connection_table.get_mut(socket_pair)
+    }
+
+    //drops a connection from the table along with its timers and its entry in the owner index
+    fn remove_connection(&mut self, socket_pair: &SocketPair) {
+        if let Some(owner) = self.connection_table.remove(socket_pair).and_then(|connection| connection.owner()) {
+            self.owned_connections.remove(&owner);
+        }
+        Self::cancel_connection_timers(&mut self.timer_wheel, &mut self.armed_timers, socket_pair);
+    }
+
+    //a receive is answered as soon as there is data, or with nothing once the peer has closed
+    fn complete_pending_receives(&mut self) {
+        let connection_table = &mut self.connection_table;
+        let owned_connections = &self.owned_connections;
+        self.pending_receives.retain(|socket_id, maximum_length| {
+            match owned_connections.get(socket_id).and_then(|socket_pair| connection_table.get_mut(socket_pair)) {
+                Some(connection) => {
+                    //the connection event the abort raises answers the client
+                    if connection.was_aborted() {
+                        return false;
+                    }
+                    if connection.has_pending_data() || connection.peer_has_closed() {
+                        UnixSocketManager::complete_receive(*socket_id, Ok(connection.read(*maximum_length)));
+                        return false;
+                    }
+                    true
+                },
+                None => {
+                    UnixSocketManager::complete_receive(*socket_id, Err("[ERROR]: connection reset".to_string()));
+                    false
+                }
+            }
+        });
+    }
+
+    //put whatever each connection has queued up on the wire
+    fn flush_connections(&mut self) {
+        let mut outbound_packet_buffer = [0u8; MTU];
+        let now = Instant::now();
+        for connection in self.connection_table.values_mut() {
+            loop {
+                let length = connection.write_next_segment(now, &mut outbound_packet_buffer);
+                if length == 0 {
+                    break;
+                }
+                Self::send_packet(&self.iface, &outbound_packet_buffer[..length]);
+            }
+        }
+    }
+
+    fn dispatch_connection_events(&mut self) {
+        let timer_wheel = &mut self.timer_wheel;
+        let armed_timers = &mut self.armed_timers;
+        let listeners = &mut self.listeners;
+        let owned_connections = &mut self.owned_connections;
+        self.connection_table.retain(|socket_pair, connection| {
+            let events = connection.take_events();
+            if let Some(owner) = connection.owner() {
+                for event in events.iter() {
+                    UnixSocketManager::notify_connection_event(owner, event);
+                }
+            }
+            if connection.is_closed() {
+                println!("[INFO]: removing closed connection {:?}", socket_pair);
+                Self::cancel_connection_timers(timer_wheel, armed_timers, socket_pair);
+                if let Some(owner) = connection.owner() {
+                    owned_connections.remove(&owner);
+                }
+                if let Some(listener) = listeners.get_mut(&socket_pair.dest_port) {
+                    listener.forget(socket_pair);
+                }
+                return false;
+            }
+            true
+        });
+    }
+
+    //a segment for a socket pair we have no connection for. A SYN to a listening port opens one,
+    //unless there are too many half-open already and it gets a SYN cookie instead, and an ACK may
+    //be returning one of those cookies. Anything else is reset. While the listener's accept queue
+    //is full both are dropped, the peer will try again.
+    fn handle_segment_without_connection(&mut self, socket_pair: SocketPair, tcpheader: &Tcp, payload: &[u8], outbound_packet_buffer: &mut [u8]) -> usize {
+        let now = Instant::now();
+        let listener = self.listeners.get(&socket_pair.dest_port);
+        let port_is_open = listener.is_some();
+        let accept_queue_is_full = listener.is_some_and(|listener| listener.accept_queue_is_full());
+        let syn_queue_is_full = listener.is_some_and(|listener| listener.syn_queue_is_full());
+        let timestamp_offset = self.isn_generator.timestamp_offset(&socket_pair);
+
+        let may_return_cookie = port_is_open
+            && tcpheader.is_ack_set()
+            && !tcpheader.is_syn_set()
+            && !tcpheader.is_rst_set()
+            && self.syn_cookie_sent_at.is_some_and(|sent_at| syncookies::still_valid(sent_at, now));
+        if may_return_cookie {
+            if let Some(cookie) = syncookies::decode(&self.isn_generator, &socket_pair, tcpheader, timestamp_offset, now) {
+                if accept_queue_is_full {
+                    println!("[INFO]: accept queue full, dropping SYN cookie ACK from {}:{}", socket_pair.src_ip, socket_pair.src_port);
+                    return 0;
+                }
+                println!("[INFO]: valid SYN cookie from {}:{}, {:?}", socket_pair.src_ip, socket_pair.src_port, cookie);
+                let mut connection = self.new_passive_connection(socket_pair, tcpheader.acknowledgement_number() - 1, now);
+                connection.restore_from_syn_cookie(now, tcpheader, &cookie);
+                if let Some(listener) = self.listeners.get_mut(&socket_pair.dest_port) {
+                    listener.complete(socket_pair);
+                }
+                let connection = self.connection_table.entry(socket_pair).or_insert(connection);
+                return Self::process_or_log(connection, now, tcpheader, payload, outbound_packet_buffer);
+            }
+        }
+
+        if !tcpheader.is_syn_set() || tcpheader.is_ack_set() || !port_is_open {
+            println!("[INFO]: no connection for {}:{} -> {}:{}, resetting",
+                socket_pair.src_ip, socket_pair.src_port, socket_pair.dest_ip, socket_pair.dest_port);
+            return Connection::write_reset_for_unexpected_segment(socket_pair, tcpheader, payload, outbound_packet_buffer);
+        }
+
+        if accept_queue_is_full {
+            println!("[INFO]: accept queue full, dropping SYN from {}:{}", socket_pair.src_ip, socket_pair.src_port);
+            return 0;
+        }
+
+        //every half-open connection sits in its listener's SYN queue, so this stays cheap under a SYN flood
+        let half_open: usize = self.listeners.values().map(|listener| listener.syn_queue_length()).sum();
+        if half_open >= self.config.syn_cookie_threshold || syn_queue_is_full {
+            println!("[INFO]: {} half-open connections, answering {}:{} with a SYN cookie", half_open, socket_pair.src_ip, socket_pair.src_port);
+            let cookie = syncookies::encode(&self.isn_generator, &socket_pair, tcpheader, now);
+            let timestamp = syncookies::timestamp(timestamp_offset, tcpheader);
+            self.syn_cookie_sent_at = Some(now);
+            return Connection::write_syn_cookie_ack(socket_pair, tcpheader, cookie, timestamp, self.config.receive_buffer_size, outbound_packet_buffer);
+        }
+
+        println!("New Connection: {}:{} -> {}:{} [SYN:{} ACK:{} FIN:{} RST:{}]",
+            socket_pair.src_ip, socket_pair.src_port,
+            socket_pair.dest_ip, socket_pair.dest_port,
+            tcpheader.is_syn_set(), tcpheader.is_ack_set(), tcpheader.is_fin_set(), tcpheader.is_rst_set());
+        let initial_sequence_number = self.isn_generator.generate(&socket_pair, now);
+        let connection = self.new_passive_connection(socket_pair, initial_sequence_number, now);
+        if let Some(listener) = self.listeners.get_mut(&socket_pair.dest_port) {
+            listener.add_half_open(socket_pair);
+        }
+        let connection = self.connection_table.entry(socket_pair).or_insert(connection);
+        Self::process_or_log(connection, now, tcpheader, payload, outbound_packet_buffer)
+    }
+
+    //a connection opened through a listening socket, starting out with the socket's options
+    fn new_passive_connection(&self, socket_pair: SocketPair, initial_sequence_number: SeqNum, now: Instant) -> Connection {
+        let mut connection = Self::new_connection(&self.isn_generator, &self.config, socket_pair, None, initial_sequence_number);
+        if let Some(listener) = self.listeners.get(&socket_pair.dest_port) {
+            for option in listener.options() {
+                connection.set_option(*option, now);
+            }
+        }
+        connection
+    }
+
+    fn process_or_log(connection: &mut Connection, now: Instant, tcpheader: &Tcp, payload: &[u8], outbound_packet_buffer: &mut [u8]) -> usize {
+        match connection.process_incoming(now, tcpheader, payload, outbound_packet_buffer) {
+            Ok(length) => length,
+            Err(e) => {
+                eprintln!("[ERROR]: {}", e);
+                0
+            }
+        }
+    }
+
+    fn cancel_connection_timers(timer_wheel: &mut TimerWheel<(SocketPair, TimerKind)>, armed_timers: &mut HashMap<(SocketPair, TimerKind), TimerId>, socket_pair: &SocketPair) {
+        for kind in TimerKind::ALL {
+            if let Some(timer_id) = armed_timers.remove(&(*socket_pair, kind)) {
+                timer_wheel.cancel(timer_id);
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, buffer: &[u8]) {
+        let nbytes = buffer.len();
+        if nbytes < 4 {
+            return;
+        }
+        let _flags = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let ethertype = u16::from_be_bytes([buffer[2], buffer[3]]);
+
+        if ethertype != 0x0800 {
+            //if not ipv4
+            return;
+        }
+
+        match Ipv4::deserialize(&buffer[4..nbytes]) {
+            Ok(ipv4header) => {
+                println!("Ipv4 header: {:?}", ipv4header);
+                if ipv4header.protocol() != 0x06 {
+                    //bail here instead of when attempting to read data into a TcpHeaderSLice later
+                    //down the line
+                    return;
+                }
+                let src = ipv4header.source_ip();
+                let dst = ipv4header.destination_ip();
+
+                match Tcp::deserialize(&buffer[4 + ipv4header.header_length_in_bytes() as usize..nbytes]) {
+                    Ok(tcpheader) => {
+                        let mut outbound_packet_buffer = [0u8; MTU];
+                        let response_size: usize;
+                        let payload_starts_at = (4 + ipv4header.header_length_in_bytes() as usize + tcpheader.header_length_in_bytes() as usize).min(nbytes);
+                        let payload = &buffer[payload_starts_at..nbytes];
+                        let socket_pair = SocketPair {
+                           src_ip : src,
+                           dest_ip : dst,
+                           dest_port : tcpheader.destination_port(),
+                           src_port : tcpheader.source_port(),
+                        };
+                        //the old connection is dropped and the SYN handled as if it had never been there
+                        let reusable = self.config.time_wait_reuse && self.connection_table.get(&socket_pair)
+                            .is_some_and(|connection| connection.allows_reuse(&tcpheader));
+                        if reusable {
+                            println!("[INFO]: new SYN takes over {:?} from TIME_WAIT", socket_pair);
+                            self.remove_connection(&socket_pair);
+                        }
+                        match self.connection_table.get_mut(&socket_pair) {
+                            Some(connection) => {
+                                let was_half_open = connection.is_half_open();
+                                let listener = self.listeners.get_mut(&socket_pair.dest_port);
+                                //no room to finish the handshake, the peer will send its ACK again
+                                if was_half_open && tcpheader.is_ack_set() && listener.as_ref().is_some_and(|listener| listener.accept_queue_is_full()) {
+                                    println!("[INFO]: accept queue full, dropping handshake ACK for {:?}", socket_pair);
+                                    return;
+                                }
+                                match connection.process_incoming(Instant::now(), &tcpheader, payload, &mut outbound_packet_buffer) {
+                                    Ok(length) => { response_size = length; },
+                                    Err(e) => {
+                                        eprintln!("[ERROR]: {}", e);
+                                        return
+                                    }
+                                }
+                                if was_half_open && !connection.is_half_open() && !connection.is_closed() {
+                                    if let Some(listener) = listener {
+                                        listener.complete(socket_pair);
+                                    }
+                                }
+                            },
+                            None => {
+                                response_size = self.handle_segment_without_connection(socket_pair, &tcpheader, payload, &mut outbound_packet_buffer);
+                            }
+                        }
+
+                        if response_size == 0 {
+                            println!("[INFO]: no response generated during processing of inbound packet.");
+                            return;
+                        }
+                        Self::send_packet(&self.iface, &outbound_packet_buffer[..response_size]);
+                    }
+                    Err(_value) => (),
+                }
+
+            },
+            Err(value) => {
+                println!("Err {:?}", value)
+            }
+        }
+    }
+
+    //write to tun interface, prefixed with the same flags/ethertype header the kernel hands us
+    fn send_packet(iface: &Iface, packet: &[u8]) {
+        let mut frame = Vec::with_capacity(4 + packet.len());
+        frame.extend_from_slice(&[0, 0, 0x08, 0x00]);
+        frame.extend_from_slice(packet);
+        match iface.send(&frame) {
+            Ok(bytecount) => {
+                println!("[INFO]: Successfully wrote {} bytes to the tunnel interface", bytecount);
+            }
+            Err(e) => {
+                eprintln!("[ERROR]: writing to tunnel interface: {}", e);
+            }
+        }
+    }
+}
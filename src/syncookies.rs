@@ -0,0 +1,218 @@
+use std::time::Instant;
+use crate::connections::SocketPair;
+use crate::isn::{IsnGenerator, SYN_COOKIE_PERIOD};
+use crate::seqnum::SeqNum;
+use crate::tcp::{Tcp, TcpOption};
+
+//RFC 4987 section 3.6 SYN cookies. Instead of keeping state for a SYN we pick an ISN that encodes
+//what the SYN asked for, and rebuild the connection from the ACK that echoes it back:
+//[counter 5 bits][MSS index 3 bits][keyed hash 24 bits]
+const COUNTER_SHIFT: u32 = 27;
+const COUNTER_MASK: u32 = 0x1F;
+const MAXIMUM_SEGMENT_SIZE_SHIFT: u32 = 24;
+const HASH_MASK: u32 = 0x00FF_FFFF;
+//only 3 bits for the MSS, so the peer's gets rounded down to one of these
+const MAXIMUM_SEGMENT_SIZES: [u16; 8] = [536, 1024, 1220, 1300, 1380, 1420, 1440, 1460];
+
+//the window scale and SACK-permitted options don't fit in the ISN as well. When the peer does
+//timestamps they go in the low bits of our TSval, which it echoes back on the ACK.
+const TIMESTAMP_OPTION_MASK: u32 = 0x3F;
+const TIMESTAMP_NO_WINDOW_SCALE: u32 = 0x0F;
+const TIMESTAMP_SACK_PERMITTED: u32 = 0x10;
+
+//what the SYN negotiated, as recovered from a valid cookie
+#[derive(Debug, Clone, Copy)]
+pub struct SynCookie {
+    pub maximum_segment_size: usize,
+    pub window_shift: Option<u8>,
+    pub sack_permitted: bool,
+}
+
+//the ISN to answer syn with
+pub fn encode(isn_generator: &IsnGenerator, socket_pair: &SocketPair, syn: &Tcp, now: Instant) -> SeqNum {
+    let peer_maximum_segment_size = syn.options().iter().find_map(|option| match option {
+        TcpOption::MaximumSegmentSize(maximum_segment_size) => Some(*maximum_segment_size),
+        _ => None,
+    }).unwrap_or(MAXIMUM_SEGMENT_SIZES[0]);
+    let index = MAXIMUM_SEGMENT_SIZES.iter().rposition(|size| *size <= peer_maximum_segment_size).unwrap_or(0) as u32;
+    let counter = isn_generator.syn_cookie_counter(now) & COUNTER_MASK;
+    let hash = isn_generator.syn_cookie_hash(socket_pair, syn.sequence_number(), counter) & HASH_MASK;
+    SeqNum::new(counter << COUNTER_SHIFT | index << MAXIMUM_SEGMENT_SIZE_SHIFT | hash)
+}
+
+//our TSval for the SYN-ACK. It sits just below the connection's timestamp offset, so whatever
+//the connection sends once rebuilt is newer as far as the peer's PAWS check is concerned.
+pub fn timestamp(timestamp_offset: u32, syn: &Tcp) -> u32 {
+    let window_shift = syn.options().iter().find_map(|option| match option {
+        TcpOption::WindowScale(shift) => Some((*shift as u32).min(TIMESTAMP_NO_WINDOW_SCALE - 1)),
+        _ => None,
+    });
+    let mut options = window_shift.unwrap_or(TIMESTAMP_NO_WINDOW_SCALE);
+    if syn.options().contains(&TcpOption::SackPermitted) {
+        options |= TIMESTAMP_SACK_PERMITTED;
+    }
+    timestamp_base(timestamp_offset) | options
+}
+
+fn timestamp_base(timestamp_offset: u32) -> u32 {
+    timestamp_offset.wrapping_sub(TIMESTAMP_OPTION_MASK + 1) & !TIMESTAMP_OPTION_MASK
+}
+
+//whether an ACK might still be returning a cookie sent at sent_at
+pub fn still_valid(sent_at: Instant, now: Instant) -> bool {
+    now.saturating_duration_since(sent_at) < SYN_COOKIE_PERIOD * 2
+}
+
+//checks the ACK completing a handshake we answered with a cookie. The cookie is good if its
+//counter is the current or the previous one and the hash matches.
+pub fn decode(isn_generator: &IsnGenerator, socket_pair: &SocketPair, ack: &Tcp, timestamp_offset: u32, now: Instant) -> Option<SynCookie> {
+    let cookie = (ack.acknowledgement_number() - 1).value();
+    let peer_initial_sequence_number = ack.sequence_number() - 1;
+    let counter = cookie >> COUNTER_SHIFT & COUNTER_MASK;
+    let current_counter = isn_generator.syn_cookie_counter(now) & COUNTER_MASK;
+    if current_counter.wrapping_sub(counter) & COUNTER_MASK > 1 {
+        return None;
+    }
+    if isn_generator.syn_cookie_hash(socket_pair, peer_initial_sequence_number, counter) & HASH_MASK != cookie & HASH_MASK {
+        return None;
+    }
+    let maximum_segment_size = MAXIMUM_SEGMENT_SIZES[(cookie >> MAXIMUM_SEGMENT_SIZE_SHIFT & 0x7) as usize] as usize;
+
+    let echo_reply = ack.options().iter().find_map(|option| match option {
+        TcpOption::Timestamps { echo_reply, .. } => Some(*echo_reply),
+        _ => None,
+    });
+    let (window_shift, sack_permitted) = match echo_reply {
+        //an echo that isn't one of our cookie TSvals means the ACK isn't what it claims to be
+        Some(echo_reply) if echo_reply & !TIMESTAMP_OPTION_MASK != timestamp_base(timestamp_offset) => return None,
+        Some(echo_reply) => {
+            let shift = echo_reply & TIMESTAMP_NO_WINDOW_SCALE;
+            let window_shift = (shift != TIMESTAMP_NO_WINDOW_SCALE).then_some(shift as u8);
+            (window_shift, echo_reply & TIMESTAMP_SACK_PERMITTED != 0)
+        },
+        //no timestamps, so nothing but the MSS survived
+        None => (None, false),
+    };
+    Some(SynCookie { maximum_segment_size, window_shift, sack_permitted })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Instant;
+    use super::{decode, encode, timestamp, SynCookie, HASH_MASK};
+    use crate::connections::{SocketPair, LOCAL_ADDRESS};
+    use crate::isn::{IsnGenerator, SYN_COOKIE_PERIOD};
+    use crate::seqnum::SeqNum;
+    use crate::tcp::{Tcp, TcpOption, FLAG_ACK, FLAG_SYN};
+
+    const PEER_ISN: u32 = 5000;
+    const TIMESTAMP_OFFSET: u32 = 0x1234_5678;
+
+    fn socket_pair(src_port: u16) -> SocketPair {
+        SocketPair {
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dest_ip: LOCAL_ADDRESS,
+            src_port,
+            dest_port: 7000,
+        }
+    }
+
+    fn syn(options: &[TcpOption]) -> Tcp {
+        let mut syn = Tcp::new(9000, 7000, SeqNum::new(PEER_ISN), SeqNum::new(0), 0x50, FLAG_SYN, 65535, 0, 0);
+        for option in options {
+            syn.push_option(option.clone()).unwrap();
+        }
+        syn
+    }
+
+    //the ACK completing the handshake, echoing the cookie and whatever TSval the SYN-ACK carried
+    fn ack(cookie: SeqNum, echo_reply: Option<u32>) -> Tcp {
+        let mut ack = Tcp::new(9000, 7000, SeqNum::new(PEER_ISN + 1), cookie + 1, 0x50, FLAG_ACK, 65535, 0, 0);
+        if let Some(echo_reply) = echo_reply {
+            ack.push_option(TcpOption::Timestamps { value: 1, echo_reply }).unwrap();
+        }
+        ack
+    }
+
+    //a SYN-ACK answered with a cookie and the ACK that comes back for it, decoded at decoded_at
+    fn round_trip(generator: &IsnGenerator, syn: &Tcp, sent_at: Instant, decoded_at: Instant) -> Option<SynCookie> {
+        let cookie = encode(generator, &socket_pair(9000), syn, sent_at);
+        let peer_does_timestamps = syn.options().iter().any(|option| matches!(option, TcpOption::Timestamps { .. }));
+        let echo_reply = peer_does_timestamps.then(|| timestamp(TIMESTAMP_OFFSET, syn));
+        decode(generator, &socket_pair(9000), &ack(cookie, echo_reply), TIMESTAMP_OFFSET, decoded_at)
+    }
+
+    #[test]
+    fn the_mss_is_rounded_down_to_one_the_cookie_can_carry() {
+        let generator = IsnGenerator::seeded(1);
+        let now = Instant::now();
+        for (peer_maximum_segment_size, expected) in [(1460, 1460), (1400, 1380), (1024, 1024), (9000, 1460), (536, 536), (100, 536)] {
+            let cookie = round_trip(&generator, &syn(&[TcpOption::MaximumSegmentSize(peer_maximum_segment_size)]), now, now).unwrap();
+            assert_eq!(cookie.maximum_segment_size, expected);
+        }
+        //RFC 9293's default when the SYN doesn't say
+        assert_eq!(round_trip(&generator, &syn(&[]), now, now).unwrap().maximum_segment_size, 536);
+    }
+
+    #[test]
+    fn window_scale_and_sack_come_back_in_the_echoed_timestamp() {
+        let generator = IsnGenerator::seeded(1);
+        let now = Instant::now();
+        let timestamps = TcpOption::Timestamps { value: 1, echo_reply: 0 };
+        let cookie = round_trip(&generator, &syn(&[TcpOption::WindowScale(7), TcpOption::SackPermitted, timestamps.clone()]), now, now).unwrap();
+        assert_eq!(cookie.window_shift, Some(7));
+        assert!(cookie.sack_permitted);
+
+        let cookie = round_trip(&generator, &syn(&[TcpOption::WindowScale(0), timestamps.clone()]), now, now).unwrap();
+        assert_eq!(cookie.window_shift, Some(0));
+        assert!(!cookie.sack_permitted);
+
+        let cookie = round_trip(&generator, &syn(&[timestamps]), now, now).unwrap();
+        assert_eq!(cookie.window_shift, None);
+        assert!(!cookie.sack_permitted);
+    }
+
+    #[test]
+    fn without_timestamps_only_the_mss_survives() {
+        let generator = IsnGenerator::seeded(1);
+        let now = Instant::now();
+        let options = [TcpOption::MaximumSegmentSize(1460), TcpOption::WindowScale(7), TcpOption::SackPermitted];
+        let cookie = round_trip(&generator, &syn(&options), now, now).unwrap();
+        assert_eq!(cookie.maximum_segment_size, 1460);
+        assert_eq!(cookie.window_shift, None);
+        assert!(!cookie.sack_permitted);
+    }
+
+    //good for the counter it was made in and the one after, not beyond
+    #[test]
+    fn cookies_from_an_old_counter_are_rejected() {
+        let generator = IsnGenerator::seeded(1);
+        let now = Instant::now();
+        let syn = syn(&[TcpOption::MaximumSegmentSize(1460)]);
+        assert!(round_trip(&generator, &syn, now, now + SYN_COOKIE_PERIOD).is_some());
+        assert!(round_trip(&generator, &syn, now, now + SYN_COOKIE_PERIOD * 2).is_none());
+    }
+
+    #[test]
+    fn cookies_that_do_not_match_are_rejected() {
+        let generator = IsnGenerator::seeded(1);
+        let now = Instant::now();
+        let syn = syn(&[TcpOption::MaximumSegmentSize(1460), TcpOption::Timestamps { value: 1, echo_reply: 0 }]);
+        let cookie = encode(&generator, &socket_pair(9000), &syn, now);
+        let echo_reply = Some(timestamp(TIMESTAMP_OFFSET, &syn));
+        assert!(decode(&generator, &socket_pair(9000), &ack(cookie, echo_reply), TIMESTAMP_OFFSET, now).is_some());
+
+        let tampered = SeqNum::new(cookie.value() ^ (HASH_MASK & 0x1));
+        assert!(decode(&generator, &socket_pair(9000), &ack(tampered, echo_reply), TIMESTAMP_OFFSET, now).is_none());
+        assert!(decode(&generator, &socket_pair(9001), &ack(cookie, echo_reply), TIMESTAMP_OFFSET, now).is_none());
+        //not the ISN the SYN carried
+        let mut wrong_sequence = ack(cookie, echo_reply);
+        wrong_sequence.set_sequence_number(SeqNum::new(PEER_ISN + 2));
+        assert!(decode(&generator, &socket_pair(9000), &wrong_sequence, TIMESTAMP_OFFSET, now).is_none());
+        //an echo that was never one of our cookie TSvals
+        assert!(decode(&generator, &socket_pair(9000), &ack(cookie, Some(TIMESTAMP_OFFSET)), TIMESTAMP_OFFSET, now).is_none());
+        //a different secret makes different cookies
+        assert!(decode(&IsnGenerator::seeded(2), &socket_pair(9000), &ack(cookie, echo_reply), TIMESTAMP_OFFSET, now).is_none());
+    }
+}
@@ -1,19 +1,166 @@
-use crate::utility::calculate_checksum;
+use crate::utility::calculate_internet_checksum;
 use std::net::Ipv4Addr;
+use crate::seqnum::SeqNum;
+
+pub const FLAG_FIN: u8 = 0b0000_0001;
+pub const FLAG_SYN: u8 = 0b0000_0010;
+pub const FLAG_RST: u8 = 0b0000_0100;
+pub const FLAG_PSH: u8 = 0b0000_1000;
+pub const FLAG_ACK: u8 = 0b0001_0000;
+pub const FLAG_URG: u8 = 0b0010_0000;
+
+const OPTION_END_OF_LIST: u8 = 0;
+const OPTION_NO_OPERATION: u8 = 1;
+const OPTION_MAXIMUM_SEGMENT_SIZE: u8 = 2;
+const OPTION_WINDOW_SCALE: u8 = 3;
+const OPTION_SACK_PERMITTED: u8 = 4;
+const OPTION_SACK: u8 = 5;
+const OPTION_TIMESTAMPS: u8 = 8;
+//the data offset is 4 bits of 32 bit words, so a header tops out at 60 bytes
+pub const MAXIMUM_OPTIONS_LENGTH: usize = 40;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TcpOption {
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    //left and right edges of up to four received blocks, right edge exclusive
+    Sack(Vec<(SeqNum, SeqNum)>),
+    Timestamps { value: u32, echo_reply: u32 },
+    //anything we don't understand is kept as is so it can at least be logged
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl TcpOption {
+
+    //parses the options area of a header, everything after the fixed 20 bytes
+    pub fn parse_all(mut data: &[u8]) -> Result<Vec<TcpOption>, &'static str> {
+        let mut options = Vec::new();
+        while let Some(&kind) = data.first() {
+            match kind {
+                OPTION_END_OF_LIST => break,
+                OPTION_NO_OPERATION => {
+                    data = &data[1..];
+                    continue;
+                },
+                _ => {}
+            }
+            if data.len() < 2 {
+                return Err("TCP option is missing its length");
+            }
+            let length = data[1] as usize;
+            if length < 2 || length > data.len() {
+                return Err("TCP option length is out of range");
+            }
+            let body = &data[2..length];
+            let option = match kind {
+                OPTION_MAXIMUM_SEGMENT_SIZE if length == 4 => {
+                    TcpOption::MaximumSegmentSize(u16::from_be_bytes([body[0], body[1]]))
+                },
+                OPTION_WINDOW_SCALE if length == 3 => TcpOption::WindowScale(body[0]),
+                OPTION_SACK_PERMITTED if length == 2 => TcpOption::SackPermitted,
+                OPTION_SACK if (10..=34).contains(&length) && (length - 2).is_multiple_of(8) => {
+                    TcpOption::Sack(body.chunks_exact(8).map(|block| (
+                        SeqNum::new(u32::from_be_bytes([block[0], block[1], block[2], block[3]])),
+                        SeqNum::new(u32::from_be_bytes([block[4], block[5], block[6], block[7]])),
+                    )).collect())
+                },
+                OPTION_TIMESTAMPS if length == 10 => TcpOption::Timestamps {
+                    value: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+                    echo_reply: u32::from_be_bytes([body[4], body[5], body[6], body[7]]),
+                },
+                OPTION_MAXIMUM_SEGMENT_SIZE
+                | OPTION_WINDOW_SCALE
+                | OPTION_SACK_PERMITTED
+                | OPTION_SACK
+                | OPTION_TIMESTAMPS => return Err("TCP option has the wrong length for its kind"),
+                _ => TcpOption::Unknown { kind, data: body.to_vec() },
+            };
+            options.push(option);
+            data = &data[length..];
+        }
+        Ok(options)
+    }
+
+    //bytes on the wire, not counting any alignment padding
+    pub fn length(&self) -> usize {
+        match self {
+            TcpOption::MaximumSegmentSize(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(blocks) => 2 + 8 * blocks.len(),
+            TcpOption::Timestamps { .. } => 10,
+            TcpOption::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    //NOPs needed in front of this option at the given offset so its fields land on the same
+    //boundaries linux puts them on
+    fn alignment_padding(&self, offset: usize) -> usize {
+        match self {
+            TcpOption::Sack(_) | TcpOption::Timestamps { .. } => (6 - offset % 4) % 4,
+            TcpOption::WindowScale(_) => (5 - offset % 4) % 4,
+            _ => 0,
+        }
+    }
+
+    fn serialize_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            TcpOption::MaximumSegmentSize(maximum_segment_size) => {
+                buffer.extend_from_slice(&[OPTION_MAXIMUM_SEGMENT_SIZE, 4]);
+                buffer.extend_from_slice(&maximum_segment_size.to_be_bytes());
+            },
+            TcpOption::WindowScale(shift) => buffer.extend_from_slice(&[OPTION_WINDOW_SCALE, 3, *shift]),
+            TcpOption::SackPermitted => buffer.extend_from_slice(&[OPTION_SACK_PERMITTED, 2]),
+            TcpOption::Sack(blocks) => {
+                buffer.extend_from_slice(&[OPTION_SACK, self.length() as u8]);
+                for (left, right) in blocks {
+                    buffer.extend_from_slice(&left.value().to_be_bytes());
+                    buffer.extend_from_slice(&right.value().to_be_bytes());
+                }
+            },
+            TcpOption::Timestamps { value, echo_reply } => {
+                buffer.extend_from_slice(&[OPTION_TIMESTAMPS, 10]);
+                buffer.extend_from_slice(&value.to_be_bytes());
+                buffer.extend_from_slice(&echo_reply.to_be_bytes());
+            },
+            TcpOption::Unknown { kind, data } => {
+                buffer.extend_from_slice(&[*kind, self.length() as u8]);
+                buffer.extend_from_slice(data);
+            },
+        }
+    }
+}
+
+//bytes the options take up in a header, padding included
+pub fn options_length(options: &[TcpOption]) -> usize {
+    serialize_options(options).len()
+}
+
+//options laid out with alignment NOPs and padded with zeroes (end of list) to a multiple of 4 bytes
+fn serialize_options(options: &[TcpOption]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for option in options {
+        let padding = option.alignment_padding(buffer.len());
+        buffer.resize(buffer.len() + padding, OPTION_NO_OPERATION);
+        option.serialize_into(&mut buffer);
+    }
+    buffer.resize(buffer.len().div_ceil(4) * 4, OPTION_END_OF_LIST);
+    buffer
+}
 
 #[derive(Debug)]
-#[repr(C, packed)]
 pub struct Tcp {
     source_port: u16,
     destination_port: u16,
-    sequence_number: u32,
-    acknowledgement_number: u32,
+    sequence_number: SeqNum,
+    acknowledgement_number: SeqNum,
     data_offset_and_reserved: u8, // Combined field for data offset (4 bits) and reserved (4 bits)
     flags: u8, // TCP flags (9 bits, but typically represented in a byte for simplicity)
     window_size: u16,
     checksum: u16,
     urgent_pointer: u16,
-    // Options would be here if needed, variable length
+    options: Vec<TcpOption>,
 }
 
 
@@ -22,25 +169,26 @@ impl Default for Tcp {
         Tcp {
             source_port: 0,
             destination_port: 0,
-            sequence_number: 0,
-            acknowledgement_number: 0,
+            sequence_number: SeqNum::new(0),
+            acknowledgement_number: SeqNum::new(0),
             data_offset_and_reserved: 0x50, // Already correctly set
             flags: 0,
             window_size: 0,
             checksum: 0,
             urgent_pointer: 0,
-            // Initialize other fields as needed
+            options: Vec::new(),
         }
     }
 }
 
 impl Tcp {
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         source_port: u16,
         destination_port: u16,
-        sequence_number: u32,
-        acknowledgement_number: u32,
+        sequence_number: SeqNum,
+        acknowledgement_number: SeqNum,
         data_offset_and_reserved: u8, // Combined field for data offset (4 bits) and reserved (4 bits)
         flags: u8, // TCP flags (9 bits, but typically represented in a byte for simplicity)
         window_size: u16,
@@ -58,6 +206,7 @@ impl Tcp {
             window_size,
             checksum,
             urgent_pointer,
+            options: Vec::new(),
         }
     }
 
@@ -69,21 +218,26 @@ impl Tcp {
         
         let source_port = (( data[0] as u16 )  << 8) | data[1] as u16;
         let destination_port = (( data[2] as u16 )  << 8) | data[3] as u16;
-        let sequence_number = ((data[4] as u32) << 24)
+        let sequence_number = SeqNum::new(((data[4] as u32) << 24)
                                 |  ((data[5] as u32) << 16) 
                                 | ((data[6] as u32) << 8) 
-                                | (data[7] as u32); 
-        let acknowledgement_number = ((data[8] as u32) << 24)
+                                | (data[7] as u32)); 
+        let acknowledgement_number = SeqNum::new(((data[8] as u32) << 24)
                                 |  ((data[9] as u32) << 16) 
                                 | ((data[10] as u32) << 8) 
-                                | (data[11] as u32); 
+                                | (data[11] as u32)); 
         let data_offset_and_reserved = data[12];
+        let header_length = (data_offset_and_reserved >> 4) as usize * 4;
+        if header_length < 20 || header_length > data.len() {
+            return Err("Data offset is out of range for TCP header");
+        }
         let flags = data[13];
         let window_size = (( data[14] as u16 )  << 8 ) | data[15] as u16;
         let checksum = (( data[16] as u16 )  << 8 ) | data[17] as u16;
         let urgent_pointer = (( data[18] as u16 )  << 8 ) | data[19] as u16;
+        let options = TcpOption::parse_all(&data[20..header_length])?;
 
-        return Ok(Tcp {
+        Ok(Tcp {
             source_port,
             destination_port,
             sequence_number,
@@ -92,22 +246,26 @@ impl Tcp {
             flags,
             window_size,
             checksum,
-            urgent_pointer
+            urgent_pointer,
+            options
         })
 
     }
 
    pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer = Vec::with_capacity(20);  // TCP header is typically 20 bytes without options
+        let options = serialize_options(&self.options);
+        let mut buffer = Vec::with_capacity(20 + options.len());
         buffer.extend_from_slice(&(self.source_port.to_be_bytes()));
         buffer.extend_from_slice(&(self.destination_port.to_be_bytes()));
-        buffer.extend_from_slice(&(self.sequence_number.to_be_bytes()));
-        buffer.extend_from_slice(&(self.acknowledgement_number.to_be_bytes()));
-        buffer.push(self.data_offset_and_reserved);
+        buffer.extend_from_slice(&(self.sequence_number.value().to_be_bytes()));
+        buffer.extend_from_slice(&(self.acknowledgement_number.value().to_be_bytes()));
+        //data offset always follows the options actually written, the reserved bits are kept
+        buffer.push(((((20 + options.len()) / 4) as u8) << 4) | (self.data_offset_and_reserved & 0x0f));
         buffer.push(self.flags);
         buffer.extend_from_slice(&(self.window_size.to_be_bytes()));
         buffer.extend_from_slice(&(self.checksum.to_be_bytes()));
         buffer.extend_from_slice(&(self.urgent_pointer.to_be_bytes()));
+        buffer.extend_from_slice(&options);
         buffer
     }
 
@@ -119,57 +277,86 @@ impl Tcp {
         self.destination_port
     }
 
+    pub fn set_destination_port(&mut self, port: u16) {
+        self.destination_port = port;
+    }
+
     pub fn source_port(&self) -> u16 {
         self.source_port
     }
 
+    pub fn set_source_port(&mut self, port: u16) {
+        self.source_port = port;
+    }
+
     pub fn window_size(&self) -> u16 {
         self.window_size
     }
 
-    pub fn sequence_number(&self) -> u32 {
+    pub fn sequence_number(&self) -> SeqNum {
         self.sequence_number
     }
 
-    pub fn set_sequence_number(&mut self, number: u32) {
+    pub fn set_sequence_number(&mut self, number: SeqNum) {
         self.sequence_number = number;
     }
 
-    pub fn acknowledgement_number(&self) -> u32 {
+    pub fn acknowledgement_number(&self) -> SeqNum {
         self.acknowledgement_number
     }
 
-    pub fn set_acknowledgement_number(&mut self, number: u32) {
+    pub fn set_acknowledgement_number(&mut self, number: SeqNum) {
         self.acknowledgement_number = number;
     }
 
+    pub fn options(&self) -> &[TcpOption] {
+        &self.options
+    }
+
+    //fails rather than produce a header whose options no longer fit in the data offset
+    pub fn push_option(&mut self, option: TcpOption) -> Result<(), &'static str> {
+        let mut options = self.options.clone();
+        options.push(option);
+        if serialize_options(&options).len() > MAXIMUM_OPTIONS_LENGTH {
+            return Err("TCP options do not fit in the header");
+        }
+        let header_length = 20 + serialize_options(&options).len();
+        self.data_offset_and_reserved = (((header_length / 4) as u8) << 4) | (self.data_offset_and_reserved & 0x0f);
+        self.options = options;
+        Ok(())
+    }
+
     pub fn is_urg_set(&self) -> bool {
-        self.flags & 0b00100000 != 0
+        self.flags & FLAG_URG != 0
     }
 
     pub fn is_ack_set(&self) -> bool {
-        self.flags & 0b00010000 != 0
+        self.flags & FLAG_ACK != 0
     }
 
     pub fn is_psh_set(&self) -> bool {
-        self.flags & 0b00001000 != 0
+        self.flags & FLAG_PSH != 0
     }
 
     pub fn is_rst_set(&self) -> bool {
-        self.flags & 0b00000100 != 0
+        self.flags & FLAG_RST != 0
     }
 
     pub fn is_syn_set(&self) -> bool {
-        self.flags & 0b00000010 != 0
+        self.flags & FLAG_SYN != 0
     }
 
     pub fn is_fin_set(&self) -> bool {
-        self.flags & 0b00000001 != 0
+        self.flags & FLAG_FIN != 0
     }
 
 
     pub fn set_syn_ack_flags(&mut self) {
-        self.flags = 0b0001_0010;
+        self.flags = FLAG_SYN | FLAG_ACK;
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.flags = flags;
     }
 
     pub fn set_window(&mut self, window_size: u16) {
@@ -182,7 +369,7 @@ impl Tcp {
         buffer.extend_from_slice(pseudoheader);
         buffer.extend_from_slice(tcpheader);
         buffer.extend_from_slice(payload);
-        calculate_checksum(&buffer) 
+        calculate_internet_checksum(&buffer)
     }
 
     pub fn create_checksum_pseudo_header(
@@ -204,7 +391,7 @@ impl Tcp {
 
     }
 
-    pub fn write_checksum(tcpheader: &mut Vec<u8>, checksum: u16) {
+    pub fn write_checksum(tcpheader: &mut [u8], checksum: u16) {
        tcpheader[16] = (checksum >> 8) as u8;
        tcpheader[17] = checksum as u8;
     }
@@ -213,3 +400,100 @@ impl Tcp {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Tcp, TcpOption, FLAG_ACK, FLAG_SYN};
+    use crate::seqnum::SeqNum;
+
+    //a bare 20 byte header followed by options, with the data offset covering them
+    fn header_with_options(options: &[u8]) -> Vec<u8> {
+        let mut header = Tcp::new(9000, 49152, SeqNum::new(1), SeqNum::new(2), 0x50, FLAG_ACK, 1000, 0, 0).serialize();
+        header[12] = (((20 + options.len()) / 4) as u8) << 4;
+        header.extend_from_slice(options);
+        header
+    }
+
+    #[test]
+    fn options_survive_a_round_trip() {
+        let options = vec![
+            TcpOption::MaximumSegmentSize(1460),
+            TcpOption::SackPermitted,
+            TcpOption::Timestamps { value: 0xdead_beef, echo_reply: 7 },
+            TcpOption::WindowScale(7),
+            TcpOption::Unknown { kind: 30, data: vec![0xab, 0xcd] },
+        ];
+        let mut tcp = Tcp::new(9000, 49152, SeqNum::new(1), SeqNum::new(0), 0x50, FLAG_SYN, 65535, 0, 0);
+        for option in options.iter().cloned() {
+            tcp.push_option(option).unwrap();
+        }
+        let serialized = tcp.serialize();
+        assert_eq!(serialized.len() % 4, 0);
+        let parsed = Tcp::deserialize(&serialized).unwrap();
+        assert_eq!(parsed.options(), options.as_slice());
+        assert_eq!(parsed.header_length_in_bytes() as usize, serialized.len());
+
+        let mut ack = Tcp::new(9000, 49152, SeqNum::new(1), SeqNum::new(0), 0x50, FLAG_ACK, 65535, 0, 0);
+        let blocks = vec![(SeqNum::new(100), SeqNum::new(200)), (SeqNum::new(u32::MAX - 10), SeqNum::new(5))];
+        ack.push_option(TcpOption::Sack(blocks.clone())).unwrap();
+        assert_eq!(Tcp::deserialize(&ack.serialize()).unwrap().options(), &[TcpOption::Sack(blocks)]);
+    }
+
+    #[test]
+    fn padding_is_skipped_and_end_of_list_stops_parsing() {
+        let options = TcpOption::parse_all(&[1, 1, 2, 4, 0x05, 0xb4, 1, 3, 3, 7, 0, 0xff, 0xff, 0xff]).unwrap();
+        assert_eq!(options, vec![TcpOption::MaximumSegmentSize(1460), TcpOption::WindowScale(7)]);
+        assert!(TcpOption::parse_all(&[0, 0, 0, 0]).unwrap().is_empty());
+        assert!(TcpOption::parse_all(&[1, 1, 1, 1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_kinds_are_kept() {
+        let options = TcpOption::parse_all(&[30, 4, 0xab, 0xcd, 31, 2]).unwrap();
+        assert_eq!(options, vec![
+            TcpOption::Unknown { kind: 30, data: vec![0xab, 0xcd] },
+            TcpOption::Unknown { kind: 31, data: Vec::new() },
+        ]);
+    }
+
+    #[test]
+    fn truncated_options_are_rejected() {
+        //kind with no length byte
+        assert!(TcpOption::parse_all(&[2]).is_err());
+        //length claims more than there is
+        assert!(TcpOption::parse_all(&[2, 4, 0x05]).is_err());
+        assert!(TcpOption::parse_all(&[8, 10, 0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn lengths_below_two_are_rejected() {
+        assert!(TcpOption::parse_all(&[2, 0, 0, 0]).is_err());
+        assert!(TcpOption::parse_all(&[30, 1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn known_kinds_with_the_wrong_length_are_rejected() {
+        assert!(TcpOption::parse_all(&[2, 3, 5, 0]).is_err());
+        assert!(TcpOption::parse_all(&[3, 4, 7, 0]).is_err());
+        assert!(TcpOption::parse_all(&[4, 3, 0, 0]).is_err());
+        //a SACK block is 8 bytes, anything else is malformed
+        assert!(TcpOption::parse_all(&[5, 6, 0, 0, 0, 1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn options_may_not_run_past_the_data_offset() {
+        //a timestamps option needs 10 bytes but the data offset only leaves room for 4, the rest
+        //of it sits where the payload starts
+        let mut segment = header_with_options(&[1, 1, 8, 10]);
+        segment.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        assert!(Tcp::deserialize(&segment).is_err());
+
+        //and a data offset past the end of the segment is no good either
+        let mut segment = header_with_options(&[]);
+        segment[12] = 6 << 4;
+        assert!(Tcp::deserialize(&segment).is_err());
+
+        let segment = header_with_options(&[1, 1, 1, 0]);
+        assert!(Tcp::deserialize(&segment).unwrap().options().is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+//resolution of the wheel, every deadline is rounded up to the next tick
+pub const TICK: Duration = Duration::from_millis(1);
+const SLOTS_PER_LEVEL_BITS: u32 = 6;
+const SLOTS_PER_LEVEL: usize = 1 << SLOTS_PER_LEVEL_BITS;
+//four levels of 64 slots at 1ms covers a little over four and a half hours, anything further out
+//is parked in the top level and cascaded down again until it is due
+const LEVELS: usize = 4;
+
+//every timer a connection can have running, a connection has at most one of each armed
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum TimerKind {
+    Connect,
+    Retransmission,
+    //the pacing rate lets the next segment go out
+    Pacing,
+    //RACK's reordering window ran out for a segment, it may now be lost
+    ReorderTimeout,
+    TailLossProbe,
+    //an ACK for in order data has been held back long enough
+    DelayedAcknowledgement,
+    //a corked partial segment has waited long enough
+    Cork,
+    //time to probe a zero window again
+    Persist,
+    //the connection has been idle long enough for the next keepalive probe
+    Keepalive,
+    //2MSL has passed since the connection entered TIME_WAIT
+    TimeWait,
+}
+
+impl TimerKind {
+    pub const ALL: [TimerKind; 10] = [
+        TimerKind::Connect,
+        TimerKind::Retransmission,
+        TimerKind::Pacing,
+        TimerKind::ReorderTimeout,
+        TimerKind::TailLossProbe,
+        TimerKind::DelayedAcknowledgement,
+        TimerKind::Cork,
+        TimerKind::Persist,
+        TimerKind::Keepalive,
+        TimerKind::TimeWait,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct TimerId(u64);
+
+//hierarchical timing wheel. Each level has 64 slots, a slot on level n spans 64^n ticks. Timers
+//land on the lowest level whose range covers them and are cascaded down a level each time the
+//level below wraps around, so scheduling and cancelling are O(1).
+pub struct TimerWheel<T> {
+    start: Instant,
+    current_tick: u64,
+    levels: Vec<Vec<Vec<TimerId>>>,
+    //cancelled timers are only dropped from here, their ids are skipped when their slot comes up
+    entries: HashMap<TimerId, (u64, T)>,
+    next_id: u64,
+}
+
+impl<T> TimerWheel<T> {
+
+    pub fn new(now: Instant) -> Self {
+        TimerWheel {
+            start: now,
+            current_tick: 0,
+            levels: (0..LEVELS).map(|_| (0..SLOTS_PER_LEVEL).map(|_| Vec::new()).collect()).collect(),
+            entries: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn schedule(&mut self, deadline: Instant, value: T) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        let expiry_tick = self.tick_for(deadline).max(self.current_tick + 1);
+        self.entries.insert(id, (expiry_tick, value));
+        self.place(id, expiry_tick);
+        id
+    }
+
+    pub fn cancel(&mut self, id: TimerId) -> Option<T> {
+        self.entries.remove(&id).map(|(_, value)| value)
+    }
+
+    //a point in time no later than the next expiry, suitable as a poll timeout. Where the next
+    //thing due sits on a higher level this is when its slot cascades rather than the deadline
+    //itself, the wheel just gets advanced once more on the way.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        //something parked on a higher level can cascade down ahead of what is already on a lower
+        //one, so every level has to be checked
+        let mut earliest_tick: Option<u64> = None;
+        for (level, slots) in self.levels.iter().enumerate() {
+            let shift = SLOTS_PER_LEVEL_BITS * level as u32;
+            let current_slot = (self.current_tick >> shift) as usize;
+            for offset in 1..=SLOTS_PER_LEVEL {
+                let slot = (current_slot + offset) % SLOTS_PER_LEVEL;
+                if slots[slot].iter().any(|id| self.entries.contains_key(id)) {
+                    let tick = ((self.current_tick >> shift) + offset as u64) << shift;
+                    earliest_tick = Some(earliest_tick.map_or(tick, |earliest| earliest.min(tick)));
+                    break;
+                }
+            }
+        }
+        Some(self.instant_for(earliest_tick.unwrap_or(self.current_tick + 1)))
+    }
+
+    //moves the wheel up to now and returns everything that expired on the way, oldest first
+    pub fn advance(&mut self, now: Instant) -> Vec<T> {
+        //rounded down so nothing fires before its deadline
+        let target_tick = (now.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64;
+        let mut expired = Vec::new();
+        while self.current_tick < target_tick {
+            if self.entries.is_empty() {
+                self.current_tick = target_tick;
+                break;
+            }
+            self.current_tick += 1;
+            self.cascade();
+            let slot = (self.current_tick as usize) % SLOTS_PER_LEVEL;
+            for id in std::mem::take(&mut self.levels[0][slot]) {
+                if let Some((_, value)) = self.entries.remove(&id) {
+                    expired.push(value);
+                }
+            }
+        }
+        expired
+    }
+
+    //when a level wraps, the matching slot of the level above gets spread back over the levels below
+    fn cascade(&mut self) {
+        for level in 1..LEVELS {
+            let lower_bits = SLOTS_PER_LEVEL_BITS * level as u32;
+            if self.current_tick & ((1u64 << lower_bits) - 1) != 0 {
+                break;
+            }
+            let slot = ((self.current_tick >> lower_bits) as usize) % SLOTS_PER_LEVEL;
+            for id in std::mem::take(&mut self.levels[level][slot]) {
+                if let Some(&(expiry_tick, _)) = self.entries.get(&id) {
+                    self.place(id, expiry_tick);
+                }
+            }
+        }
+    }
+
+    fn place(&mut self, id: TimerId, expiry_tick: u64) {
+        let delta = expiry_tick.saturating_sub(self.current_tick);
+        let mut level = 0;
+        while level < LEVELS - 1 && delta >= 1u64 << (SLOTS_PER_LEVEL_BITS * (level as u32 + 1)) {
+            level += 1;
+        }
+        let shift = SLOTS_PER_LEVEL_BITS * level as u32;
+        //past the end of the top level, park it in the furthest slot and let cascading bring it back
+        let maximum_tick = self.current_tick + ((SLOTS_PER_LEVEL as u64 - 1) << shift);
+        let slot = ((expiry_tick.min(maximum_tick) >> shift) as usize) % SLOTS_PER_LEVEL;
+        self.levels[level][slot].push(id);
+    }
+
+    //rounded up so a timer never fires before its deadline
+    fn tick_for(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.start);
+        elapsed.as_nanos().div_ceil(TICK.as_nanos()) as u64
+    }
+
+    fn instant_for(&self, tick: u64) -> Instant {
+        self.start + Duration::from_nanos(TICK.as_nanos() as u64 * tick)
+    }
+}
+
+//the timers one connection wants running. The connection arms and cancels by kind, the stack picks
+//the changes up afterwards and mirrors them into its wheel.
+#[derive(Default)]
+pub struct ConnectionTimers {
+    deadlines: HashMap<TimerKind, Instant>,
+    changes: Vec<(TimerKind, Option<Instant>)>,
+}
+
+impl ConnectionTimers {
+
+    pub fn arm(&mut self, kind: TimerKind, deadline: Instant) {
+        self.deadlines.insert(kind, deadline);
+        self.changes.push((kind, Some(deadline)));
+    }
+
+    pub fn cancel(&mut self, kind: TimerKind) {
+        if self.deadlines.remove(&kind).is_some() {
+            self.changes.push((kind, None));
+        }
+    }
+
+    pub fn cancel_all(&mut self) {
+        for kind in TimerKind::ALL {
+            self.cancel(kind);
+        }
+    }
+
+    pub fn is_armed(&self, kind: TimerKind) -> bool {
+        self.deadlines.contains_key(&kind)
+    }
+
+    //called when the stack reports a timer fired, false if it was re-armed for later or cancelled
+    //in the meantime and the expiry should be ignored
+    pub fn expire(&mut self, kind: TimerKind, now: Instant) -> bool {
+        match self.deadlines.get(&kind) {
+            Some(deadline) if *deadline <= now => {
+                self.deadlines.remove(&kind);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn take_changes(&mut self) -> Vec<(TimerKind, Option<Instant>)> {
+        std::mem::take(&mut self.changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{TimerWheel, LEVELS, SLOTS_PER_LEVEL, TICK};
+
+    fn millis(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn nothing_fires_before_its_deadline() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        //not on a tick, so it waits for the next one
+        wheel.schedule(millis(start, 10) + Duration::from_micros(1), "late");
+        assert!(wheel.advance(millis(start, 10)).is_empty());
+        assert_eq!(wheel.advance(millis(start, 11)), vec!["late"]);
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    //one deadline on each of the lower levels, each only fires once the levels above have
+    //cascaded it down
+    #[test]
+    fn timers_cascade_down_across_level_boundaries() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        let level_one = SLOTS_PER_LEVEL as u64 + 5;
+        let level_two = (SLOTS_PER_LEVEL * SLOTS_PER_LEVEL) as u64 + 70;
+        wheel.schedule(millis(start, level_two), level_two);
+        wheel.schedule(millis(start, level_one), level_one);
+        wheel.schedule(millis(start, 3), 3);
+
+        for deadline in [3, level_one, level_two] {
+            assert!(wheel.advance(millis(start, deadline - 1)).is_empty());
+            assert_eq!(wheel.advance(millis(start, deadline)), vec![deadline]);
+        }
+    }
+
+    #[test]
+    fn expired_timers_come_back_oldest_first() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        for deadline in [5000, 7, 300, 64, 4096] {
+            wheel.schedule(millis(start, deadline), deadline);
+        }
+        assert_eq!(wheel.advance(millis(start, 10_000)), vec![7, 64, 300, 4096, 5000]);
+    }
+
+    #[test]
+    fn cancelled_timers_never_fire() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        let cancelled = wheel.schedule(millis(start, 100), "cancelled");
+        let kept = wheel.schedule(millis(start, 200), "kept");
+        assert_eq!(wheel.cancel(cancelled), Some("cancelled"));
+        assert_eq!(wheel.cancel(cancelled), None);
+        assert_eq!(wheel.advance(millis(start, 300)), vec!["kept"]);
+        assert_eq!(wheel.cancel(kept), None);
+
+        let cancelled = wheel.schedule(millis(start, 10_000), "cancelled");
+        wheel.cancel(cancelled);
+        assert_eq!(wheel.next_expiry(), None);
+        assert!(wheel.advance(millis(start, 20_000)).is_empty());
+    }
+
+    //further out than the top level reaches, it sits in its furthest slot until cascading brings
+    //it back within range
+    #[test]
+    fn timers_beyond_the_top_level_are_parked_until_due() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        let wheel_span = TICK * (SLOTS_PER_LEVEL as u32).pow(LEVELS as u32);
+        let deadline = start + wheel_span + Duration::from_secs(60);
+        wheel.schedule(deadline, "parked");
+
+        let next = wheel.next_expiry().unwrap();
+        assert!(next <= deadline);
+        assert!(wheel.advance(deadline - TICK).is_empty());
+        assert_eq!(wheel.advance(deadline), vec!["parked"]);
+    }
+
+    //following next_expiry around never oversleeps a deadline, whichever level it is on
+    #[test]
+    fn next_expiry_is_never_after_the_earliest_deadline() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new(start);
+        let mut pending: Vec<u64> = Vec::new();
+        let mut state: u64 = 12345;
+        for _ in 0..200 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let deadline = 1 + (state >> 33) % 600_000;
+            wheel.schedule(millis(start, deadline), deadline);
+            pending.push(deadline);
+        }
+        pending.sort_unstable();
+
+        while let Some(next) = wheel.next_expiry() {
+            assert!(next <= millis(start, pending[0]));
+            for fired in wheel.advance(next) {
+                assert!(millis(start, fired) <= next);
+                assert_eq!(fired, pending.remove(0));
+            }
+        }
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::{Duration, Instant};
+use crate::seqnum::SeqNum;
+use crate::tcp::TcpOption;
+
+//RFC 7323 section 5.5, a TS.Recent this old can't be trusted for PAWS any more
+const MAXIMUM_TIMESTAMP_AGE: Duration = Duration::from_secs(24 * 24 * 60 * 60);
+
+//RFC 7323 timestamps for one connection. Our clock ticks once a millisecond starting from a
+//per-connection offset, so TSval says nothing about uptime or other connections.
+pub struct Timestamps {
+    enabled: bool,
+    clock_start: Instant,
+    offset: u32,
+    //TS.Recent, the peer's timestamp we echo back, and when it was last updated
+    recent: u32,
+    recent_updated: Option<Instant>,
+    //Last.ACK.sent, the acknowledgement number of the last ACK we sent
+    last_acknowledgement_sent: Option<SeqNum>,
+}
+
+impl Timestamps {
+
+    pub fn new(offset: u32, now: Instant) -> Self {
+        Timestamps {
+            enabled: false,
+            clock_start: now,
+            offset,
+            recent: 0,
+            recent_updated: None,
+            last_acknowledgement_sent: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_offset(&mut self, offset: u32) {
+        self.offset = offset;
+    }
+
+    //TSval for a segment sent now
+    pub fn value(&self, now: Instant) -> u32 {
+        (now.saturating_duration_since(self.clock_start).as_millis() as u32).wrapping_add(self.offset)
+    }
+
+    //section 3.2, timestamps are only used when the peer's SYN carried them too
+    pub fn negotiate(&mut self, peer_timestamps: Option<(u32, u32)>, now: Instant) {
+        match peer_timestamps {
+            Some((value, _)) => {
+                self.enabled = true;
+                self.recent = value;
+                self.recent_updated = Some(now);
+            },
+            None => {
+                self.enabled = false;
+                self.recent_updated = None;
+            }
+        }
+    }
+
+    //the option for an outbound segment, echoing whatever TS.Recent currently is
+    pub fn option(&self, now: Instant) -> TcpOption {
+        TcpOption::Timestamps { value: self.value(now), echo_reply: self.recent }
+    }
+
+    //section 5.3, PAWS: a segment carrying a timestamp older than TS.Recent is an old duplicate
+    pub fn is_old_duplicate(&self, peer_value: u32, now: Instant) -> bool {
+        match self.recent_updated {
+            Some(updated) if now.saturating_duration_since(updated) < MAXIMUM_TIMESTAMP_AGE => {
+                (peer_value.wrapping_sub(self.recent) as i32) < 0
+            },
+            _ => false,
+        }
+    }
+
+    //whether peer_value comes after TS.Recent, RFC 6191 lets a SYN reopen TIME_WAIT if it does
+    pub fn is_newer(&self, peer_value: u32) -> bool {
+        (peer_value.wrapping_sub(self.recent) as i32) > 0
+    }
+
+    pub fn acknowledgement_sent(&mut self, acknowledgement_number: SeqNum) {
+        self.last_acknowledgement_sent = Some(acknowledgement_number);
+    }
+
+    //section 4.3, only a segment starting at or before Last.ACK.sent moves TS.Recent, and only ever
+    //forward. That way what we echo is the timestamp of the segment that caused our next ACK, not
+    //one from further along that happened to arrive first.
+    pub fn update_recent(&mut self, peer_value: u32, sequence_number: SeqNum, now: Instant) {
+        if !self.last_acknowledgement_sent.is_some_and(|last| sequence_number.le(last)) {
+            return;
+        }
+        if self.recent_updated.is_none() || (peer_value.wrapping_sub(self.recent) as i32) >= 0 {
+            self.recent = peer_value;
+            self.recent_updated = Some(now);
+        }
+    }
+
+    //section 4.1, the echoed timestamp of an ACK gives an RTT sample even for retransmitted data
+    pub fn rtt_sample(&self, echo_reply: u32, now: Instant) -> Option<Duration> {
+        if !self.enabled || echo_reply == 0 {
+            return None;
+        }
+        let elapsed = self.value(now).wrapping_sub(echo_reply);
+        //an echo from the future is bogus
+        if (elapsed as i32) < 0 {
+            return None;
+        }
+        Some(Duration::from_millis(elapsed as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{Timestamps, MAXIMUM_TIMESTAMP_AGE};
+    use crate::seqnum::SeqNum;
+    use crate::tcp::TcpOption;
+
+    const OFFSET: u32 = 1000;
+
+    fn negotiated(peer_value: u32, now: Instant) -> Timestamps {
+        let mut timestamps = Timestamps::new(OFFSET, now);
+        timestamps.negotiate(Some((peer_value, 0)), now);
+        timestamps
+    }
+
+    //TS.Recent, as echoed on the next segment we send
+    fn echoed(timestamps: &Timestamps, now: Instant) -> u32 {
+        match timestamps.option(now) {
+            TcpOption::Timestamps { echo_reply, .. } => echo_reply,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn paws_rejects_timestamps_older_than_recent() {
+        let now = Instant::now();
+        let timestamps = negotiated(5000, now);
+        assert!(timestamps.is_old_duplicate(4999, now));
+        assert!(!timestamps.is_old_duplicate(5000, now));
+        assert!(!timestamps.is_old_duplicate(5001, now));
+        //a TS.Recent nobody has refreshed in 24 days says nothing any more
+        assert!(!timestamps.is_old_duplicate(4999, now + MAXIMUM_TIMESTAMP_AGE));
+
+        //the peer's clock wrapping around is still newer
+        let timestamps = negotiated(u32::MAX - 10, now);
+        assert!(!timestamps.is_old_duplicate(5, now));
+        assert!(timestamps.is_old_duplicate(u32::MAX - 11, now));
+    }
+
+    #[test]
+    fn recent_only_moves_for_segments_up_to_the_last_ack_sent() {
+        let now = Instant::now();
+        let mut timestamps = negotiated(100, now);
+        //nothing acknowledged yet, so nothing can be at or before it
+        timestamps.update_recent(200, SeqNum::new(5000), now);
+        assert_eq!(echoed(&timestamps, now), 100);
+
+        timestamps.acknowledgement_sent(SeqNum::new(5000));
+        //data further along than what we last acknowledged
+        timestamps.update_recent(200, SeqNum::new(6000), now);
+        assert_eq!(echoed(&timestamps, now), 100);
+        timestamps.update_recent(200, SeqNum::new(5000), now);
+        assert_eq!(echoed(&timestamps, now), 200);
+        //an older timestamp never takes it backwards, even on a segment that qualifies
+        timestamps.update_recent(150, SeqNum::new(4000), now);
+        assert_eq!(echoed(&timestamps, now), 200);
+    }
+
+    #[test]
+    fn rtt_sample_comes_from_the_echoed_timestamp() {
+        let start = Instant::now();
+        let timestamps = negotiated(100, start);
+        let now = start + Duration::from_millis(50);
+        assert_eq!(timestamps.value(now), OFFSET + 50);
+        assert_eq!(timestamps.rtt_sample(OFFSET + 10, now), Some(Duration::from_millis(40)));
+        //zero means the peer isn't echoing anything yet
+        assert_eq!(timestamps.rtt_sample(0, now), None);
+        assert_eq!(timestamps.rtt_sample(OFFSET + 60, now), None);
+
+        let mut disabled = Timestamps::new(OFFSET, start);
+        disabled.negotiate(None, start);
+        assert_eq!(disabled.rtt_sample(OFFSET + 10, now), None);
+    }
+}
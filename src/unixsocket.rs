@@ -1,40 +1,66 @@
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::sync::{Arc,Mutex};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Sender};
 use std::collections::HashMap;
+use std::net::Ipv4Addr;
 use std::thread;
-use std::io::Read;
+use std::time::Duration;
+use std::io::{Read, Write};
 use lazy_static::lazy_static;
+use crate::congestion::CongestionAlgorithm;
+use crate::connections::{ConnectionEvent, SocketPair, LOCAL_ADDRESS};
+use crate::events::{CommandSender, SocketCommand, SocketOption};
+use crate::listener::DEFAULT_BACKLOG;
+use crate::mitigations;
 
 const SOCKET_PATH: &str = "/tmp/wtfistcp_unix_socket";
+const EPHEMERAL_PORT_RANGE_START: u16 = 49152;
+//option ids a SetOption message may carry
+const OPTION_CONGESTION_CONTROL: u8 = 1;
+const OPTION_QUICK_ACK: u8 = 2;
+const OPTION_NO_DELAY: u8 = 3;
+const OPTION_CORK: u8 = 4;
+const OPTION_KEEPALIVE: u8 = 5;
+const OPTION_KEEPALIVE_IDLE: u8 = 6;
+const OPTION_KEEPALIVE_INTERVAL: u8 = 7;
+const OPTION_KEEPALIVE_COUNT: u8 = 8;
 
 struct ClientConnection {
-    stream: Arc<Mutex<UnixStream>>,
+    //responses for the client's unix stream, written out by that client's writer thread
+    responses: ResponseSender,
     socket_state: SocketState,
-    bound_port: Option<u16>
+    bound_port: Option<u16>,
+    //options set so far, handed to the stack along with the connect or listen
+    options: Vec<SocketOption>
 }
 
 
 pub struct UnixSocketManager {
 }
 
+//frames queued for one client. Only its writer thread ever writes to the stream, so neither the
+//main loop nor a request thread can be held up by a client that has stopped reading.
+type ResponseSender = Sender<Vec<u8>>;
+
 lazy_static! {
     static ref CONNECTIONS_TABLE: Mutex<HashMap<u32, ClientConnection>> = Mutex::new(HashMap::new());
     static ref ID_COUNTER: Mutex<u32> = Mutex::new(0);
+    static ref EPHEMERAL_PORT_COUNTER: Mutex<u16> = Mutex::new(EPHEMERAL_PORT_RANGE_START);
 }
 
 
 impl UnixSocketManager {
 
     
-    pub fn initialize() -> Result<(),std::io::Error>  {
+    pub fn initialize(command_sender: CommandSender) -> Result<(),std::io::Error>  {
         let listener = UnixListener::bind(SOCKET_PATH)?;
         thread::spawn(move || {
             for stream in listener.incoming() {
             // accept connections and process them, spawning a new thread for each one
                 match stream {
                     Ok(stream) => {
-                        let stream_arc = Arc::new(Mutex::new(stream));
-                        thread::spawn(|| Self::handle_client(stream_arc));
+                        let command_sender = command_sender.clone();
+                        thread::spawn(move || Self::handle_client(stream, command_sender));
                     }
                     Err(_err) => {
                         /* connection failed */
@@ -47,23 +73,41 @@ impl UnixSocketManager {
         Ok(())
     }
 
-    fn handle_client (  stream: Arc<Mutex<UnixStream>> ) {
+    fn handle_client (  mut read_stream: UnixStream, command_sender: CommandSender ) {
         let mut payload_buffer : Vec<u8>;
         let mut header_buffer = [0u8; 5];
 
+        //responses come from the main loop as well as this thread, they all go through a queue
+        //to a thread of their own that does the writing on a separate handle
+        let mut write_stream = match read_stream.try_clone() {
+            Ok(write_stream) => write_stream,
+            Err(e) => {
+                eprintln!("[ERROR]: failed to clone unix stream {e}");
+                return;
+            }
+        };
+        let (responses, queued_responses) = mpsc::channel::<Vec<u8>>();
+        thread::spawn(move || {
+            //ends once every socket of this client is gone and nothing can queue any more
+            for response in queued_responses {
+                if let Err(e) = write_stream.write_all(&response) {
+                    eprintln!("[ERROR]: failed to write unix socket response {e}");
+                    break;
+                }
+            }
+        });
+
         loop {
-            let mut stream_guard = stream.lock().unwrap();
-            match stream_guard.read_exact(&mut header_buffer) {
+            match read_stream.read_exact(&mut header_buffer) {
                 Ok(()) => {
-                    let payload_size = u32::from_be_bytes(header_buffer[1..4].try_into().unwrap()) as usize;
+                    let payload_size = u32::from_be_bytes(header_buffer[1..5].try_into().unwrap()) as usize;
                     let message_type = MessageType::from_byte(header_buffer[0]);
 
                     payload_buffer = vec![0u8; payload_size];
 
-                    match stream_guard.read_exact(&mut payload_buffer) {
+                    match read_stream.read_exact(&mut payload_buffer) {
                         Ok(()) => {
-                            let mut response_buffer = Vec::new();
-                            Self::handle_message( message_type, &payload_buffer, &mut response_buffer, &stream);
+                            Self::handle_message( message_type, &payload_buffer, &responses, &command_sender);
                         }
                         Err(e) => {
                             eprintln!("[ERROR]: problem when reading into unix socket payload buffer {e}");
@@ -79,30 +123,63 @@ impl UnixSocketManager {
         }
     }
 
-    fn handle_message(  message_type: Result<MessageType, &'static str>, payload: &[u8], response_buffer: &mut Vec::<u8>, stream: &Arc<Mutex<UnixStream>> ) {
+    fn handle_message(  message_type: Result<MessageType, &'static str>, payload: &[u8], responses: &ResponseSender, command_sender: &CommandSender ) {
         match message_type {
             Ok(mt) => {
-                match mt {
+                //every request but Socket and Stats starts with the id of the socket it is for
+                let mut socket_id = payload.get(..4).map_or(0, |id| u32::from_be_bytes([id[0], id[1], id[2], id[3]]));
+                let mut response_payload = Vec::new();
+                let result = match mt {
                     MessageType::Connect => {
+                        Self::handle_connect_message(payload, command_sender)
                     },
                     MessageType::Send => {
+                        Self::handle_send_message(payload, command_sender)
                     },
                     MessageType::Receive => {
+                        Self::handle_receive_message(payload, command_sender)
                     },
                     MessageType::Close => {
+                        Self::handle_close_message(payload, command_sender)
                     },
                     MessageType::Accept => {
+                        Self::handle_accept_message(payload, command_sender)
                     },
                     MessageType::Listen => {
-                        Self::handle_listen_message(payload, response_buffer, stream).unwrap();
+                        Self::handle_listen_message(payload, command_sender)
                     },
                     MessageType::Bind => {
-                        Self::handle_bind_message(payload, response_buffer, stream).unwrap();
+                        Self::handle_bind_message(payload)
                     },
                     MessageType::Socket => {
-                        Self::handle_socket_message( &payload, response_buffer, stream).unwrap();
+                        socket_id = Self::handle_socket_message(responses);
+                        Ok(())
                     },
+                    MessageType::SetOption => {
+                        Self::handle_set_option_message(payload, command_sender)
+                    },
+                    MessageType::Event => {
+                        Err("[ERROR]: events are only sent by the stack")
+                    },
+                    MessageType::Stats => {
+                        socket_id = 0;
+                        response_payload = Self::handle_stats_message();
+                        Ok(())
+                    },
+                };
+                //these get answered by the main loop once the stack has dealt with them
+                let deferred = matches!(mt, MessageType::Connect | MessageType::Send | MessageType::Receive | MessageType::Listen | MessageType::Accept);
+                if deferred && result.is_ok() {
+                    return;
                 }
+                let status = match result {
+                    Ok(()) => ResponseStatus::Ok,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        ResponseStatus::Error
+                    }
+                };
+                Self::write_response(responses, mt, socket_id, status, &response_payload);
             }
             Err(_) => {
                 eprintln!("[ERROR]: Invalid message type received");
@@ -110,21 +187,53 @@ impl UnixSocketManager {
         }
     }
 
-    fn handle_socket_message(  payload: &[u8], response_buffer: &mut Vec::<u8>, stream: &Arc<Mutex<UnixStream>> ) -> Result<(), &'static str> {
-        // let port_number: u16 = u16::from_be_bytes([payload[0], payload[1]]);
+    //responses mirror the request framing, with the id of the socket they are about after the
+    //status: [message type][u32 length][status][socket id u32][payload]. Accepted sockets share
+    //the listening socket's unix stream, the id is what tells their responses apart. A Socket
+    //response carries the id it just handed out, anything that didn't name a socket gets 0.
+    fn write_response( responses: &ResponseSender, message_type: MessageType, socket_id: u32, status: ResponseStatus, payload: &[u8] ) {
+        let mut response = Vec::with_capacity(10 + payload.len());
+        response.push(message_type as u8);
+        response.extend_from_slice(&(5 + payload.len() as u32).to_be_bytes());
+        response.push(status as u8);
+        response.extend_from_slice(&socket_id.to_be_bytes());
+        response.extend_from_slice(payload);
+        //the client is gone if its writer thread is
+        if responses.send(response).is_err() {
+            eprintln!("[ERROR]: unix socket client is no longer taking responses");
+        }
+    }
+
+    fn handle_socket_message(  responses: &ResponseSender ) -> u32 {
         let new_client_connection = ClientConnection {
-            stream: stream.clone(),
+            responses: responses.clone(),
             bound_port: None,
-            socket_state: SocketState::Created
+            socket_state: SocketState::Created,
+            options: Vec::new()
         };
         let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
         let unique_fd = Self::get_next_unique_fd_id();
         connections_table_lock.insert(unique_fd, new_client_connection);
-        //TODO - add some kind of response here?
-        Ok(())
+        unique_fd
+    }
+
+    //no payload. Answered with the stack wide RFC 5961 counters, each a u64: resets out of window,
+    //resets challenged, SYNs challenged, ACKs out of range, challenge ACKs held back by the limit
+    fn handle_stats_message() -> Vec<u8> {
+        let counters = mitigations::counters();
+        [
+            counters.resets_out_of_window,
+            counters.resets_challenged,
+            counters.syns_challenged,
+            counters.acknowledgements_out_of_range,
+            counters.challenge_acks_rate_limited,
+        ].iter().flat_map(|counter| counter.to_be_bytes()).collect()
     }
 
-    fn handle_bind_message(  payload: &[u8], response_buffer: &mut Vec::<u8>, stream: &Arc<Mutex<UnixStream>> ) -> Result<(), &'static str> {
+    fn handle_bind_message(  payload: &[u8] ) -> Result<(), &'static str> {
+        if payload.len() < 6 {
+            return Err("[ERROR]: bind message too short");
+        }
         let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
         let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
         let desired_port: u16 = u16::from_be_bytes([payload[4], payload[5]]);
@@ -142,51 +251,343 @@ impl UnixSocketManager {
                 return Err("[ERROR]: could not find unix connection when attempting to bind");
             }
         }
-        //TODO - add some kind of response here?
         Ok(())
     }
 
-    fn handle_listen_message(  payload: &[u8], response_buffer: &mut Vec::<u8>, stream: &Arc<Mutex<UnixStream>> ) -> Result<(), &'static str> {
+    //payload: [socket id u32][backlog u32], the backlog may be left off. Answered once the stack
+    //has the listener in place, so a SYN sent as soon as this returns can't miss it
+    fn handle_listen_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 4 {
+            return Err("[ERROR]: listen message too short");
+        }
         let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
         let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
-        match connections_table_lock.get_mut(&unique_fd) {
+        let backlog = match payload.get(4..8) {
+            Some(backlog) => u32::from_be_bytes([backlog[0], backlog[1], backlog[2], backlog[3]]) as usize,
+            None => DEFAULT_BACKLOG,
+        };
+        let (port, options) = match connections_table_lock.get_mut(&unique_fd) {
             Some(connection) => {
+                if !matches!(connection.socket_state, SocketState::Bound | SocketState::Listening) {
+                    return Err("[ERROR]: cannot listen on a socket that has not been bound");
+                }
+                let Some(port) = connection.bound_port else {
+                    return Err("[ERROR]: cannot listen on a socket that has not been bound");
+                };
                 connection.socket_state = SocketState::Listening;
+                (port, connection.options.clone())
             },
             None => {
-                return Err("[ERROR]: could not find unix connection when attempting to bind");
+                return Err("[ERROR]: could not find unix connection when attempting to listen");
             }
+        };
+        command_sender.send(SocketCommand::Listen { socket_id: unique_fd, port, backlog, options })
+    }
+
+    //payload: [socket id u32][non-blocking u8], blocking if the flag is left off. Answered with the
+    //new socket's id and the peer's address once a connection is ready
+    fn handle_accept_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 4 {
+            return Err("[ERROR]: accept message too short");
+        }
+        let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
+        let nonblocking = payload.get(4).is_some_and(|flag| *flag != 0);
+        let connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        match connections_table_lock.get(&unique_fd) {
+            Some(connection) if matches!(connection.socket_state, SocketState::Listening) => {
+                command_sender.send(SocketCommand::Accept { socket_id: unique_fd, nonblocking })
+            },
+            Some(_) => Err("[ERROR]: socket is not listening"),
+            None => Err("[ERROR]: could not find unix connection when attempting to accept"),
+        }
+    }
+
+    //payload: [socket id u32][remote ipv4 address][remote port u16]
+    fn handle_connect_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 10 {
+            return Err("[ERROR]: connect message too short");
+        }
+        let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
+        let remote_ip = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+        let remote_port: u16 = u16::from_be_bytes([payload[8], payload[9]]);
+
+        let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        let bound_port = match connections_table_lock.get(&unique_fd) {
+            Some(connection) if matches!(connection.socket_state, SocketState::Created | SocketState::Bound) => connection.bound_port,
+            Some(_) => return Err("[ERROR]: socket cannot connect in its current state"),
+            None => return Err("[ERROR]: could not find unix connection when attempting to connect"),
+        };
+        let options = connections_table_lock[&unique_fd].options.clone();
+        let local_port = match bound_port {
+            Some(port) => port,
+            None => Self::allocate_ephemeral_port(&connections_table_lock)?,
+        };
+        if let Some(connection) = connections_table_lock.get_mut(&unique_fd) {
+            connection.bound_port = Some(local_port);
+            connection.socket_state = SocketState::Connecting;
+        }
+
+        let socket_pair = SocketPair {
+            src_ip: remote_ip,
+            dest_ip: LOCAL_ADDRESS,
+            src_port: remote_port,
+            dest_port: local_port,
+        };
+        command_sender.send(SocketCommand::Connect { socket_id: unique_fd, socket_pair, options })
+    }
+
+    //payload: [socket id u32][option u8][value...]. Remembered on the socket, and applied straight
+    //away if it already has a connection
+    fn handle_set_option_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 5 {
+            return Err("[ERROR]: set option message too short");
+        }
+        let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
+        let option = Self::parse_socket_option(payload[4], &payload[5..])?;
+
+        let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        let Some(connection) = connections_table_lock.get_mut(&unique_fd) else {
+            return Err("[ERROR]: could not find unix connection when attempting to set an option");
+        };
+        connection.options.retain(|existing| std::mem::discriminant(existing) != std::mem::discriminant(&option));
+        connection.options.push(option);
+        if matches!(connection.socket_state, SocketState::Connecting | SocketState::Connected) {
+            command_sender.send(SocketCommand::SetOption { socket_id: unique_fd, option })?;
         }
-        //TODO - add some kind of response here?
         Ok(())
     }
 
-    fn get_next_unique_fd_id () -> u32 {
-        let mut num = ID_COUNTER.lock().unwrap();
-        *num += 1; 
-        *num
+    fn parse_socket_option( option: u8, value: &[u8] ) -> Result<SocketOption, &'static str> {
+        match option {
+            OPTION_CONGESTION_CONTROL => {
+                let algorithm = value.first().ok_or("[ERROR]: congestion control option needs a value")?;
+                Ok(SocketOption::CongestionControl(CongestionAlgorithm::from_byte(*algorithm)?))
+            },
+            OPTION_QUICK_ACK => {
+                let enabled = value.first().ok_or("[ERROR]: quick ack option needs a value")?;
+                Ok(SocketOption::QuickAck(*enabled != 0))
+            },
+            OPTION_NO_DELAY => {
+                let enabled = value.first().ok_or("[ERROR]: no delay option needs a value")?;
+                Ok(SocketOption::NoDelay(*enabled != 0))
+            },
+            OPTION_CORK => {
+                let enabled = value.first().ok_or("[ERROR]: cork option needs a value")?;
+                Ok(SocketOption::Cork(*enabled != 0))
+            },
+            OPTION_KEEPALIVE => {
+                let enabled = value.first().ok_or("[ERROR]: keepalive option needs a value")?;
+                Ok(SocketOption::KeepAlive(*enabled != 0))
+            },
+            //seconds, as u32
+            OPTION_KEEPALIVE_IDLE => Ok(SocketOption::KeepIdle(Duration::from_secs(Self::positive_option_value(value)? as u64))),
+            OPTION_KEEPALIVE_INTERVAL => Ok(SocketOption::KeepInterval(Duration::from_secs(Self::positive_option_value(value)? as u64))),
+            OPTION_KEEPALIVE_COUNT => Ok(SocketOption::KeepCount(Self::positive_option_value(value)?)),
+            _ => Err("[ERROR]: unknown socket option"),
+        }
     }
 
-    pub fn port_is_open (port: u16) -> bool {
-        let connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
-        for connection in connections_table_lock.values() {
-            if let Some(bound_port) = connection.bound_port {
-                if bound_port == port && matches!(connection.socket_state, SocketState::Listening) {
-                    return true;
+    fn positive_option_value( value: &[u8] ) -> Result<u32, &'static str> {
+        if value.len() < 4 {
+            return Err("[ERROR]: option needs a u32 value");
+        }
+        match u32::from_be_bytes([value[0], value[1], value[2], value[3]]) {
+            0 => Err("[ERROR]: option value must be at least 1"),
+            value => Ok(value),
+        }
+    }
+
+    //payload: [socket id u32][data...]
+    fn handle_send_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        let unique_fd = Self::connected_socket_id(payload)?;
+        let data = payload[4..].to_vec();
+        command_sender.send(SocketCommand::Send { socket_id: unique_fd, data })
+    }
+
+    //payload: [socket id u32][maximum length u32]
+    fn handle_receive_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 8 {
+            return Err("[ERROR]: receive message too short");
+        }
+        let unique_fd = Self::connected_socket_id(payload)?;
+        let maximum_length = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize;
+        command_sender.send(SocketCommand::Receive { socket_id: unique_fd, maximum_length })
+    }
+
+    //payload: [socket id u32]. The socket id is released straight away, the stack finishes the
+    //close handshake on its own
+    fn handle_close_message(  payload: &[u8], command_sender: &CommandSender ) -> Result<(), &'static str> {
+        if payload.len() < 4 {
+            return Err("[ERROR]: close message too short");
+        }
+        let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
+        let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        match connections_table_lock.remove(&unique_fd) {
+            Some(connection) => {
+                if matches!(connection.socket_state, SocketState::Connecting | SocketState::Connected | SocketState::Listening) {
+                    command_sender.send(SocketCommand::Close { socket_id: unique_fd })?;
                 }
+                Ok(())
+            },
+            None => Err("[ERROR]: could not find unix connection when attempting to close"),
+        }
+    }
+
+    fn connected_socket_id( payload: &[u8] ) -> Result<u32, &'static str> {
+        if payload.len() < 4 {
+            return Err("[ERROR]: message too short");
+        }
+        let unique_fd: u32 = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3] ]);
+        let connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        match connections_table_lock.get(&unique_fd) {
+            Some(connection) if matches!(connection.socket_state, SocketState::Connected) => Ok(unique_fd),
+            Some(_) => Err("[ERROR]: socket is not connected"),
+            None => Err("[ERROR]: could not find unix connection"),
+        }
+    }
+
+    fn allocate_ephemeral_port( connections_table: &HashMap<u32, ClientConnection> ) -> Result<u16, &'static str> {
+        let mut next_port = EPHEMERAL_PORT_COUNTER.lock().unwrap();
+        for _ in EPHEMERAL_PORT_RANGE_START..=u16::MAX {
+            let candidate = *next_port;
+            *next_port = if candidate == u16::MAX { EPHEMERAL_PORT_RANGE_START } else { candidate + 1 };
+            if !connections_table.values().any(|conn| conn.bound_port == Some(candidate)) {
+                return Ok(candidate);
+            }
+        }
+        Err("[ERROR]: no ephemeral ports left")
+    }
+
+    //answers a deferred Send with how many bytes the stack accepted
+    pub fn complete_send(socket_id: u32, result: Result<usize, String>) {
+        match result {
+            Ok(length) => Self::respond_to(socket_id, MessageType::Send, ResponseStatus::Ok, &(length as u32).to_be_bytes()),
+            Err(e) => {
+                eprintln!("{e}");
+                Self::respond_to(socket_id, MessageType::Send, ResponseStatus::Error, &[]);
             }
         }
-        false
+    }
+
+    //answers a deferred Receive, an empty payload means the peer has closed its side
+    pub fn complete_receive(socket_id: u32, result: Result<Vec<u8>, String>) {
+        match result {
+            Ok(data) => Self::respond_to(socket_id, MessageType::Receive, ResponseStatus::Ok, &data),
+            Err(e) => {
+                eprintln!("{e}");
+                Self::respond_to(socket_id, MessageType::Receive, ResponseStatus::Error, &[]);
+            }
+        }
+    }
+
+    //answers a deferred Listen
+    pub fn complete_listen(socket_id: u32, result: Result<(), String>) {
+        match result {
+            Ok(()) => Self::respond_to(socket_id, MessageType::Listen, ResponseStatus::Ok, &[]),
+            Err(e) => {
+                eprintln!("{e}");
+                Self::respond_to(socket_id, MessageType::Listen, ResponseStatus::Error, &[]);
+            }
+        }
+    }
+
+    //answers a deferred Accept with [new socket id u32][peer ipv4 address][peer port u16]. The new
+    //socket talks over the listening socket's unix stream and starts out connected. None if the
+    //listening socket has gone away in the meantime.
+    pub fn complete_accept(listener_id: u32, socket_pair: SocketPair, options: Vec<SocketOption>) -> Option<u32> {
+        let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        let responses = connections_table_lock.get(&listener_id)?.responses.clone();
+        let unique_fd = Self::get_next_unique_fd_id();
+        connections_table_lock.insert(unique_fd, ClientConnection {
+            responses: responses.clone(),
+            socket_state: SocketState::Connected,
+            bound_port: Some(socket_pair.dest_port),
+            options
+        });
+        let mut payload = Vec::with_capacity(10);
+        payload.extend_from_slice(&unique_fd.to_be_bytes());
+        payload.extend_from_slice(&socket_pair.src_ip.octets());
+        payload.extend_from_slice(&socket_pair.src_port.to_be_bytes());
+        Self::write_response(&responses, MessageType::Accept, listener_id, ResponseStatus::Ok, &payload);
+        Some(unique_fd)
+    }
+
+    //a non-blocking accept with nothing in the accept queue
+    pub fn accept_would_block(listener_id: u32) {
+        Self::respond_to(listener_id, MessageType::Accept, ResponseStatus::WouldBlock, &[]);
+    }
+
+    fn respond_to(socket_id: u32, message_type: MessageType, status: ResponseStatus, payload: &[u8]) {
+        let connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        if let Some(connection) = connections_table_lock.get(&socket_id) {
+            Self::write_response(&connection.responses, message_type, socket_id, status, payload);
+        }
+    }
+
+    //called from the main loop once a connection owned by a unix socket client changes state
+    pub fn notify_connection_event(socket_id: u32, event: &ConnectionEvent) {
+        let mut connections_table_lock = CONNECTIONS_TABLE.lock().unwrap();
+        let Some(connection) = connections_table_lock.get_mut(&socket_id) else {
+            return;
+        };
+        match event {
+            ConnectionEvent::Established => {
+                if matches!(connection.socket_state, SocketState::Connecting) {
+                    connection.socket_state = SocketState::Connected;
+                    Self::write_response(&connection.responses, MessageType::Connect, socket_id, ResponseStatus::Ok, &[]);
+                }
+            },
+            ConnectionEvent::Reset | ConnectionEvent::TimedOut => {
+                let reset = *event == ConnectionEvent::Reset;
+                match connection.socket_state {
+                    SocketState::Connecting => {
+                        let status = if reset { ResponseStatus::ConnectionRefused } else { ResponseStatus::TimedOut };
+                        connection.socket_state = SocketState::Created;
+                        connection.bound_port = None;
+                        Self::write_response(&connection.responses, MessageType::Connect, socket_id, status, &[]);
+                    },
+                    //nothing was asked, but the client has to find out its connection is gone. A
+                    //Receive still waiting gets no answer of its own, this is it.
+                    SocketState::Connected => {
+                        let status = if reset { ResponseStatus::ConnectionReset } else { ResponseStatus::TimedOut };
+                        connection.socket_state = SocketState::Disconnected;
+                        Self::write_response(&connection.responses, MessageType::Event, socket_id, status, &[]);
+                    },
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    fn get_next_unique_fd_id () -> u32 {
+        let mut num = ID_COUNTER.lock().unwrap();
+        *num += 1; 
+        *num
     }
 }
 
 enum SocketState {
     Created,
     Bound,
-    Listening
+    Listening,
+    Connecting,
+    Connected,
+    //the connection was reset or timed out, only a Close is left to do
+    Disconnected
+}
+
+#[repr(u8)]
+enum ResponseStatus {
+    Ok = 0,
+    Error = 1,
+    ConnectionRefused = 2,
+    TimedOut = 3,
+    WouldBlock = 4,
+    ConnectionReset = 5,
 }
 
 
+#[derive(Clone, Copy)]
 enum MessageType {
     Connect = 1,
     Send = 2,
@@ -196,6 +597,10 @@ enum MessageType {
     Listen = 6,
     Bind = 7,
     Socket = 8,
+    SetOption = 9,
+    //sent by us only, when a connected socket's connection goes away
+    Event = 10,
+    Stats = 11,
 }
 
 impl MessageType {
@@ -209,9 +614,93 @@ impl MessageType {
             6 => Ok(Self::Listen),
             7 => Ok(Self::Bind),
             8 => Ok(Self::Socket),
+            9 => Ok(Self::SetOption),
+            11 => Ok(Self::Stats),
             _ => Err("[ERROR] invalid message type received over unix socket")
         }
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use super::{ClientConnection, MessageType, ResponseStatus, SocketState, UnixSocketManager, CONNECTIONS_TABLE};
+    use crate::connections::{Connection, SocketPair, LOCAL_ADDRESS};
+    use crate::events::SocketOption;
+    use crate::seqnum::SeqNum;
+    use crate::stack::MTU;
+    use crate::tcp::{Tcp, FLAG_ACK, FLAG_SYN};
+    use crate::timer::TimerKind;
+
+    const LOCAL_PORT: u16 = 49152;
+    const PEER_PORT: u16 = 9000;
+
+    //a connected socket whose peer stops answering keepalive probes is told on its queue, the
+    //same way the main loop hands connection events over
+    #[test]
+    fn keepalive_timeout_is_reported_to_a_connected_socket() {
+        let (responses, queued_responses) = mpsc::channel();
+        let socket_id = UnixSocketManager::get_next_unique_fd_id();
+        CONNECTIONS_TABLE.lock().unwrap().insert(socket_id, ClientConnection {
+            responses,
+            socket_state: SocketState::Connected,
+            bound_port: Some(LOCAL_PORT),
+            options: Vec::new()
+        });
+
+        let socket_pair = SocketPair {
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dest_ip: LOCAL_ADDRESS,
+            src_port: PEER_PORT,
+            dest_port: LOCAL_PORT,
+        };
+        let mut outbound_buffer = [0u8; MTU];
+        let mut now = Instant::now();
+        let mut connection = Connection::new_active(socket_pair, socket_id, SeqNum::new(1000));
+        connection.open(now, &mut outbound_buffer).unwrap();
+        let syn_ack = Tcp::new(PEER_PORT, LOCAL_PORT, SeqNum::new(5000), SeqNum::new(1001), 0x50, FLAG_SYN | FLAG_ACK, 65535, 0, 0);
+        connection.process_incoming(now, &syn_ack, &[], &mut outbound_buffer).unwrap();
+        for option in [
+            SocketOption::KeepIdle(Duration::from_secs(1)),
+            SocketOption::KeepInterval(Duration::from_secs(1)),
+            SocketOption::KeepCount(2),
+            SocketOption::KeepAlive(true),
+        ] {
+            connection.set_option(option, now);
+        }
+        while connection.write_next_segment(now, &mut outbound_buffer) > 0 {}
+
+        //nothing ever comes back, so every time the keepalive timer goes off it is for a probe
+        //until the count runs out
+        let mut probes = 0;
+        while !connection.was_aborted() {
+            now = connection.take_timer_changes().into_iter()
+                .rev()
+                .filter(|(kind, _)| *kind == TimerKind::Keepalive)
+                .find_map(|(_, deadline)| deadline)
+                .expect("keepalive timer should be armed");
+            connection.on_timer(TimerKind::Keepalive, now);
+            while connection.write_next_segment(now, &mut outbound_buffer) > 0 {
+                probes += 1;
+            }
+        }
+        assert_eq!(probes, 2);
+
+        for event in connection.take_events() {
+            UnixSocketManager::notify_connection_event(socket_id, &event);
+        }
+        let frame: Vec<u8> = queued_responses.try_recv().unwrap();
+        assert_eq!(frame.len(), 10);
+        assert_eq!(frame[0], MessageType::Event as u8);
+        assert_eq!(u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]), 5);
+        assert_eq!(frame[5], ResponseStatus::TimedOut as u8);
+        assert_eq!(u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]), socket_id);
+
+        let connection = CONNECTIONS_TABLE.lock().unwrap().remove(&socket_id).unwrap();
+        assert!(matches!(connection.socket_state, SocketState::Disconnected));
+    }
+}
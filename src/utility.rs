@@ -1,16 +1,28 @@
+//ipv4 header checksum, the checksum field itself (bytes 10 and 11) is skipped so this works on
+//headers that already carry one
 pub fn calculate_checksum(header: &[u8]) -> u16 {
+    ones_complement_checksum(header, Some(10))
+}
+
+//plain internet checksum over everything, used for the tcp pseudo header + segment where byte 10
+//is part of the tcp length and must not be skipped
+pub fn calculate_internet_checksum(data: &[u8]) -> u16 {
+    ones_complement_checksum(data, None)
+}
+
+fn ones_complement_checksum(data: &[u8], skip_word_at: Option<usize>) -> u16 {
 
     let mut sum = 0u32;
 
-    for (i, &byte) in header.iter().enumerate().step_by(2) {
+    for (i, &byte) in data.iter().enumerate().step_by(2) {
 
-        if  i == 10  {
+        if  Some(i) == skip_word_at  {
             continue;
         }
 
         let mut temp2bytes = (byte as u16) << 8;
-        if i + 1 < header.len() {
-            temp2bytes = temp2bytes | (header[i+1] as u16);
+        if i + 1 < data.len() {
+            temp2bytes |= data[i+1] as u16;
         }
         sum = sum.wrapping_add(temp2bytes as u32);
     };
@@ -25,5 +37,3 @@ pub fn calculate_checksum(header: &[u8]) -> u16 {
     !(sum as u16)
 
 }
-
-